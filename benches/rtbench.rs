@@ -1,11 +1,26 @@
+use std::rc::Rc;
+
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
-use raytracer::{self, camera::Camera, hittable::HittableList, sphere::Sphere, util::vec::Point3};
+use raytracer::{
+    self,
+    camera::Camera,
+    hittable::HittableList,
+    material::{Cutout, Lambertian, Material},
+    sphere::Sphere,
+    texture::{CheckerTexture, SolidColor},
+    util::{color::Color, vec::Point3},
+};
 
-pub fn simple_scene(c: &mut Criterion) {
+fn scene(mat: Rc<dyn Material>) -> HittableList {
     let mut world = HittableList::new();
 
-    world.add(Box::new(Sphere::new(Point3::new(0, 0, -1), 0.5)));
-    world.add(Box::new(Sphere::new(Point3::new(0, -100.5, -1), 100)));
+    world.add(Rc::new(Sphere::new(Point3::new(0, 0, -1), 0.5, mat.clone())));
+    world.add(Rc::new(Sphere::new(Point3::new(0, -100.5, -1), 100, mat)));
+    return world;
+}
+
+pub fn simple_scene(c: &mut Criterion) {
+    let world = scene(Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
 
     let mut cam: Camera = Camera::new();
 
@@ -23,5 +38,34 @@ pub fn simple_scene(c: &mut Criterion) {
     g.finish();
 }
 
-criterion_group!(benches, simple_scene);
+/// Cost of consulting opacity inside `Hittable::hit`: an opaque material skips the
+/// texture lookup, a fully opaque cutout pays for the lookup alone, and the checker
+/// masks also pay for the rays continuing through the holes
+pub fn cutouts(c: &mut Criterion) {
+    let base: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let checker = Rc::new(CheckerTexture::from_colors(0.1, Color::from(0.0), Color::from(1.0)));
+    let materials: [(&str, Rc<dyn Material>); 4] = [
+        ("Opaque", base.clone()),
+        (
+            "FullyOpaqueCutout",
+            Rc::new(Cutout::new(base.clone(), Rc::new(SolidColor::new(Color::from(1.0))))),
+        ),
+        ("CheckerThreshold", Rc::new(Cutout::new(base.clone(), checker.clone()).with_threshold(0.5))),
+        ("CheckerStochastic", Rc::new(Cutout::new(base, checker))),
+    ];
+
+    let mut cam: Camera = Camera::new();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 256;
+    cam.samples_per_pixel = 2;
+
+    let mut g = c.benchmark_group("Cutout");
+    for (name, mat) in materials {
+        let world = scene(mat);
+        g.bench_function(name, |b| b.iter(|| cam._render_quiet(black_box(&world))));
+    }
+    g.finish();
+}
+
+criterion_group!(benches, simple_scene, cutouts);
 criterion_main!(benches);
@@ -0,0 +1,200 @@
+use std::{io, path::Path};
+
+use crate::util::{color::Color, image::Image, vec::Vec3};
+
+/// Auxiliary output recorded next to the beauty image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Aov {
+    /// Distance from the camera to the first hit
+    Depth,
+    /// World space shading normal at the first hit, facing the camera
+    Normal,
+    /// Material reflectance at the first hit
+    Albedo,
+    /// World space position of the first hit
+    Position,
+    ObjectId,
+    MaterialId,
+    /// Light reflected to the camera by the first hit that came straight from a
+    /// light or the environment
+    Direct,
+    /// Light reflected to the camera by the first hit that bounced at least once more
+    Indirect,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 8] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::Position,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Direct,
+        Aov::Indirect,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        };
+    }
+
+    /// IDs can't be blended, so ID passes keep a pixel's first sample instead of the mean
+    fn is_id(&self) -> bool {
+        return matches!(self, Aov::ObjectId | Aov::MaterialId);
+    }
+}
+
+/// Everything one camera sample contributes to the AOVs. Rays that miss the scene
+/// leave the geometric values at zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AovSample {
+    pub depth: f64,
+    pub normal: Vec3,
+    pub albedo: Color,
+    pub position: Vec3,
+    pub object_id: u32,
+    pub material_id: u32,
+    pub direct: Color,
+    pub indirect: Color,
+}
+
+impl AovSample {
+    /// Files light emitted at path vertex `bounce` under direct or indirect. Light seen
+    /// straight from the camera belongs to neither.
+    pub fn add_emission(&mut self, bounce: i32, c: Color) {
+        match bounce {
+            0 => {}
+            1 => self.direct += c,
+            _ => self.indirect += c,
+        }
+    }
+
+    fn value(&self, aov: Aov) -> Color {
+        return match aov {
+            Aov::Depth => Color::from(self.depth),
+            Aov::Normal => self.normal,
+            Aov::Albedo => self.albedo,
+            Aov::Position => self.position,
+            Aov::ObjectId => Color::from(self.object_id as f64),
+            Aov::MaterialId => Color::from(self.material_id as f64),
+            Aov::Direct => self.direct,
+            Aov::Indirect => self.indirect,
+        };
+    }
+}
+
+/// Per pixel accumulation of the enabled AOVs, unfiltered
+#[derive(Debug, Clone)]
+pub struct AovBuffers {
+    width: usize,
+    height: usize,
+    layers: Vec<(Aov, Vec<Color>)>,
+    count: Vec<u32>,
+}
+
+impl AovBuffers {
+    pub fn new(width: usize, height: usize, aovs: &[Aov]) -> Self {
+        let mut layers: Vec<(Aov, Vec<Color>)> = Vec::new();
+        for &aov in aovs {
+            if !layers.iter().any(|(a, _)| *a == aov) {
+                layers.push((aov, vec![Color::default(); width * height]));
+            }
+        }
+        Self {
+            width: width,
+            height: height,
+            layers: layers,
+            count: vec![0; width * height],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.layers.is_empty();
+    }
+
+    pub fn add_sample(&mut self, i: usize, j: usize, sample: &AovSample) {
+        let idx = j * self.width + i;
+        let first = self.count[idx] == 0;
+        for (aov, data) in &mut self.layers {
+            if aov.is_id() {
+                if first {
+                    data[idx] = sample.value(*aov);
+                }
+            } else {
+                data[idx] += sample.value(*aov);
+            }
+        }
+        self.count[idx] += 1;
+    }
+
+    pub fn image(&self, aov: Aov) -> Option<Image> {
+        let (_, data) = self.layers.iter().find(|(a, _)| *a == aov)?;
+        let pixels = data
+            .iter()
+            .zip(&self.count)
+            .map(|(&c, &n)| {
+                if aov.is_id() || n == 0 {
+                    c
+                } else {
+                    c / n as f64
+                }
+            })
+            .collect();
+        return Some(Image::from_data(self.width, self.height, pixels));
+    }
+
+    pub fn images(&self) -> Vec<(Aov, Image)> {
+        return self
+            .layers
+            .iter()
+            .map(|(aov, _)| (*aov, self.image(*aov).unwrap()))
+            .collect();
+    }
+}
+
+/// Writes every image as its own PFM file named `<prefix>.<aov>.pfm`
+pub fn save_aovs(images: &[(Aov, Image)], prefix: &Path) -> io::Result<()> {
+    for (aov, img) in images {
+        let mut path = prefix.as_os_str().to_owned();
+        path.push(format!(".{}.pfm", aov.name()));
+        img.save_pfm(path)?;
+    }
+    return Ok(());
+}
+
+#[test]
+fn aov_buffers_average_and_keep_first_id() {
+    let mut aovs = AovBuffers::new(1, 1, &[Aov::Depth, Aov::ObjectId, Aov::Depth]);
+    aovs.add_sample(
+        0,
+        0,
+        &AovSample {
+            depth: 2.0,
+            object_id: 7,
+            ..Default::default()
+        },
+    );
+    aovs.add_sample(
+        0,
+        0,
+        &AovSample {
+            depth: 4.0,
+            object_id: 9,
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(aovs.images().len(), 2);
+    assert_eq!(aovs.image(Aov::Depth).unwrap().get(0, 0), Color::from(3.0));
+    assert_eq!(aovs.image(Aov::ObjectId).unwrap().get(0, 0), Color::from(7.0));
+    assert!(aovs.image(Aov::Normal).is_none());
+}
@@ -0,0 +1,273 @@
+use std::rc::Rc;
+
+use crate::{
+    hittable::{HitRecord, Hittable, SceneIds},
+    material::Material,
+    texture::Texture,
+    util::{
+        aabb::Aabb,
+        color::Color,
+        interval::Interval,
+        onb::Onb,
+        ray::Ray,
+        vec::{cross, dot, Vec3},
+    },
+};
+
+/// Step in texture coordinates for the finite differences of bump maps
+const BUMP_DELTA: f64 = 5e-4;
+
+/// Fine surface detail faked by bending the shading normal, leaving the geometry as
+/// it is. Displacing the geometry itself would need a mesh to tessellate, which the
+/// renderer doesn't have.
+#[derive(Debug, Clone)]
+pub enum NormalPerturbation {
+    /// Normal of the surface displaced along its normal by the first channel of
+    /// `height` times `scale`
+    Bump { height: Rc<dyn Texture>, scale: f64 },
+    /// Tangent space normal map with x along `dpdu`, y along `dpdv` and z along the
+    /// normal, encoded as `(n + 1) / 2`. `strength` scales the tilt away from the
+    /// surface normal.
+    NormalMap { map: Rc<dyn Texture>, strength: f64 },
+}
+
+impl NormalPerturbation {
+    pub fn bump(height: Rc<dyn Texture>, scale: f64) -> Self {
+        return NormalPerturbation::Bump {
+            height: height,
+            scale: scale,
+        };
+    }
+
+    pub fn normal_map(map: Rc<dyn Texture>) -> Self {
+        return NormalPerturbation::NormalMap { map: map, strength: 1.0 };
+    }
+
+    /// Replaces `rec.normal` with the perturbed normal, keeping it on the side the ray
+    /// came from. Hits without a normal, such as medium collisions, are left alone.
+    pub fn apply(&self, rec: &mut HitRecord) {
+        let n = rec.normal;
+        if n.near_zero() {
+            return;
+        }
+        let (dpdu, dpdv) = if rec.dpdu.near_zero() || rec.dpdv.near_zero() {
+            let basis = Onb::new(n);
+            (basis.u(), basis.v())
+        } else {
+            (rec.dpdu, rec.dpdv)
+        };
+        // Both perturbations work around the outward normal, so a back face gets the
+        // mirror image of the front one once the result is flipped towards the ray
+        let n_outward = if dot(cross(dpdu, dpdv), n) < 0.0 { -n } else { n };
+
+        let perturbed = match self {
+            NormalPerturbation::Bump { height, scale } => {
+                let h = |du: f64, dv: f64| {
+                    let p = rec.p + du * dpdu + dv * dpdv;
+                    scale * height.value(rec.u + du, rec.v + dv, p).x()
+                };
+                let h0 = h(0.0, 0.0);
+                let dhdu = (h(BUMP_DELTA, 0.0) - h0) / BUMP_DELTA;
+                let dhdv = (h(0.0, BUMP_DELTA) - h0) / BUMP_DELTA;
+                // Derivatives of the displaced surface p + h n, with the normal's own
+                // change left out as it is small for shallow bumps
+                cross(dpdu + dhdu * n_outward, dpdv + dhdv * n_outward)
+            }
+            NormalPerturbation::NormalMap { map, strength } => {
+                let c = map.value(rec.u, rec.v, rec.p);
+                let local = Vec3::new(
+                    strength * (2.0 * c.x() - 1.0),
+                    strength * (2.0 * c.y() - 1.0),
+                    2.0 * c.z() - 1.0,
+                );
+                let t = (dpdu - dot(dpdu, n_outward) * n_outward).to_normal();
+                let b = cross(n_outward, t);
+                let b = if dot(b, dpdv) < 0.0 { -b } else { b };
+                local.x() * t + local.y() * b + local.z() * n_outward
+            }
+        };
+        if perturbed.near_zero() {
+            return;
+        }
+        let perturbed = perturbed.to_normal();
+        rec.normal = if dot(perturbed, n) < 0.0 { -perturbed } else { perturbed };
+    }
+}
+
+/// Material whose normal is perturbed before `inner` sees the hit, so every object
+/// using it gets the same surface detail
+#[derive(Debug, Clone)]
+pub struct PerturbedMaterial {
+    inner: Rc<dyn Material>,
+    perturbation: NormalPerturbation,
+}
+
+impl PerturbedMaterial {
+    pub fn new(inner: Rc<dyn Material>, perturbation: NormalPerturbation) -> Self {
+        Self {
+            inner: inner,
+            perturbation: perturbation,
+        }
+    }
+
+    fn shade(&self, rec: &HitRecord) -> HitRecord {
+        let mut shaded = rec.clone();
+        self.perturbation.apply(&mut shaded);
+        return shaded;
+    }
+}
+
+impl Material for PerturbedMaterial {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        return self.inner.scatter(r_in, &self.shade(rec), attenuation, scattered);
+    }
+
+    fn scatter_with_pdf(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> Option<f64> {
+        return self.inner.scatter_with_pdf(r_in, &self.shade(rec), attenuation, scattered);
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        return self.inner.emitted(r_in, rec);
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        return self.inner.eval(r_in, &self.shade(rec), wi);
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        return self.inner.pdf(r_in, &self.shade(rec), wi);
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        return self.inner.albedo(rec);
+    }
+
+    fn opacity(&self, rec: &HitRecord) -> f64 {
+        return self.inner.opacity(rec);
+    }
+
+    fn is_dispersive(&self) -> bool {
+        return self.inner.is_dispersive();
+    }
+}
+
+/// Object whose hits get a perturbed normal whatever their material, which also shows
+/// up in the normal pass
+pub struct PerturbedObject {
+    object: Rc<dyn Hittable>,
+    perturbation: NormalPerturbation,
+}
+
+impl PerturbedObject {
+    pub fn new(object: Rc<dyn Hittable>, perturbation: NormalPerturbation) -> Self {
+        Self {
+            object: object,
+            perturbation: perturbation,
+        }
+    }
+}
+
+impl Hittable for PerturbedObject {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        if !self.object.hit(r, ray_t, rec) {
+            return false;
+        }
+        self.perturbation.apply(rec);
+        return true;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.object.bounding_box();
+    }
+
+    fn assign_ids(&self, ids: &mut SceneIds) {
+        self.object.assign_ids(ids);
+    }
+}
+
+#[cfg(test)]
+fn flat_hit() -> HitRecord {
+    let mut rec = HitRecord::default();
+    rec.normal = Vec3::new(0, 0, 1);
+    rec.dpdu = Vec3::new(1, 0, 0);
+    rec.dpdv = Vec3::new(0, 1, 0);
+    return rec;
+}
+
+#[cfg(test)]
+struct Ramp;
+
+#[cfg(test)]
+impl Texture for Ramp {
+    fn value(&self, u: f64, _v: f64, _p: crate::util::vec::Point3) -> Color {
+        return Color::from(u);
+    }
+}
+
+#[test]
+fn bump_tilts_normal_against_the_slope() {
+    let mut rec = flat_hit();
+    NormalPerturbation::bump(Rc::new(Ramp), 1.0).apply(&mut rec);
+    // Height rising along u at 45 degrees
+    let expected = Vec3::new(-1, 0, 1).to_normal();
+    assert!((rec.normal - expected).length() < 1e-9, "{:?}", rec.normal);
+
+    // From behind the normal stays on the ray's side
+    let mut back = flat_hit();
+    back.normal = Vec3::new(0, 0, -1);
+    NormalPerturbation::bump(Rc::new(Ramp), 1.0).apply(&mut back);
+    assert!((back.normal + expected).length() < 1e-9, "{:?}", back.normal);
+}
+
+#[test]
+fn normal_map_follows_tangent_frame() {
+    use crate::texture::SolidColor;
+
+    let mut rec = flat_hit();
+    NormalPerturbation::normal_map(Rc::new(SolidColor::new(Color::new(0.5, 0.5, 1.0)))).apply(&mut rec);
+    assert!((rec.normal - Vec3::new(0, 0, 1)).near_zero());
+
+    let tilt = || NormalPerturbation::normal_map(Rc::new(SolidColor::new(Color::new(1.0, 0.5, 1.0))));
+    let mut tilted = flat_hit();
+    tilt().apply(&mut tilted);
+    let expected = Vec3::new(1, 0, 1).to_normal();
+    assert!((tilted.normal - expected).near_zero(), "{:?}", tilted.normal);
+
+    // From behind the normal is the front one turned towards the ray
+    let mut back = flat_hit();
+    back.normal = Vec3::new(0, 0, -1);
+    tilt().apply(&mut back);
+    assert!((back.normal + expected).near_zero(), "{:?}", back.normal);
+}
+
+#[test]
+fn wrapped_cutouts_keep_their_mask() {
+    use crate::{
+        material::{Cutout, Lambertian, Layered, Mix},
+        texture::SolidColor,
+    };
+
+    let cutout: Rc<dyn Material> = Rc::new(Cutout::new(
+        Rc::new(Lambertian::default()),
+        Rc::new(SolidColor::new(Color::from(0.25))),
+    ));
+    let rec = flat_hit();
+    let bumped = PerturbedMaterial::new(cutout.clone(), NormalPerturbation::bump(Rc::new(Ramp), 1.0));
+    assert_eq!(bumped.opacity(&rec), 0.25);
+    assert_eq!(Layered::new(cutout.clone(), 1.5).opacity(&rec), 0.25);
+    // Blended with an opaque material by the mix weight
+    let mixed = Mix::new(cutout, Rc::new(Lambertian::default()), 0.5);
+    assert!((mixed.opacity(&rec) - 0.625).abs() < 1e-12);
+}
@@ -0,0 +1,137 @@
+use std::rc::Rc;
+
+use crate::{
+    hittable::{HitRecord, Hittable, HittableList, SceneIds},
+    util::{aabb::Aabb, interval::Interval, ray::Ray},
+};
+
+pub struct BvhNode {
+    left: Rc<dyn Hittable>,
+    right: Rc<dyn Hittable>,
+    bbox: Aabb,
+}
+
+impl BvhNode {
+    pub fn from_list(list: &HittableList) -> Self {
+        let mut objects = list.objects().to_vec();
+        let end = objects.len();
+        return Self::new(&mut objects, 0, end);
+    }
+
+    pub fn new(objects: &mut Vec<Rc<dyn Hittable>>, start: usize, end: usize) -> Self {
+        let mut bbox = Aabb::empty();
+        for o in &objects[start..end] {
+            bbox = Aabb::enclosing(&bbox, &o.bounding_box());
+        }
+
+        let axis = bbox.longest_axis();
+        let span = end - start;
+
+        let (left, right): (Rc<dyn Hittable>, Rc<dyn Hittable>) = match span {
+            0 => {
+                let empty: Rc<dyn Hittable> = Rc::new(HittableList::new());
+                (empty.clone(), empty)
+            }
+            1 => (objects[start].clone(), objects[start].clone()),
+            2 => (objects[start].clone(), objects[start + 1].clone()),
+            _ => {
+                objects[start..end].sort_by(|a, b| {
+                    let a_min = a.bounding_box().axis_interval(axis).min;
+                    let b_min = b.bounding_box().axis_interval(axis).min;
+                    a_min.total_cmp(&b_min)
+                });
+                let mid = start + span / 2;
+                (
+                    Rc::new(BvhNode::new(objects, start, mid)),
+                    Rc::new(BvhNode::new(objects, mid, end)),
+                )
+            }
+        };
+
+        Self {
+            left: left,
+            right: right,
+            bbox: bbox,
+        }
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        if !self.bbox.hit(r, ray_t) {
+            return false;
+        }
+
+        let hit_left = self.left.hit(r, ray_t, rec);
+        // Nodes over a single object hold it on both sides, and cutouts and media draw
+        // random numbers, so hitting it twice would change the result
+        if Rc::ptr_eq(&self.left, &self.right) {
+            return hit_left;
+        }
+        let closest = if hit_left { rec.t } else { ray_t.max };
+        let hit_right = self.right.hit(r, Interval::new(ray_t.min, closest), rec);
+
+        return hit_left || hit_right;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.bbox;
+    }
+
+    fn assign_ids(&self, ids: &mut SceneIds) {
+        self.left.assign_ids(ids);
+        // Nodes over a single object hold it on both sides
+        if !Rc::ptr_eq(&self.left, &self.right) {
+            self.right.assign_ids(ids);
+        }
+    }
+}
+
+#[test]
+fn cut_out_hits_leave_the_record_alone() {
+    use crate::{
+        material::{Cutout, Lambertian},
+        sphere::Sphere,
+        texture::SolidColor,
+        util::{color::Color, vec::{Point3, Vec3}},
+    };
+
+    let opaque: Rc<dyn Hittable> = Rc::new(Sphere::new(Point3::new(0, 0, -4), 0.5, Rc::new(Lambertian::default())));
+    let hole = Rc::new(Cutout::new(Rc::new(Lambertian::default()), Rc::new(SolidColor::new(Color::from(0.0)))));
+    // Nearer, but the ray goes straight through it
+    let cut: Rc<dyn Hittable> = Rc::new(Sphere::new(Point3::new(0, 0.2, -2), 0.5, hole));
+    let bvh = BvhNode::new(&mut vec![opaque, cut], 0, 2);
+
+    let r = Ray::new(Point3::new(0, 0, 0), Vec3::new(0, 0, -1));
+    let mut rec = HitRecord::default();
+    assert!(bvh.hit(&r, Interval::new(0, f64::INFINITY), &mut rec));
+    assert!((rec.t - 3.5).abs() < 1e-9, "{}", rec.t);
+    assert!((rec.normal - Vec3::new(0, 0, 1)).near_zero(), "{:?}", rec.normal);
+}
+
+#[test]
+fn single_objects_are_hit_once() {
+    use crate::{
+        material::{Cutout, Lambertian},
+        sphere::Sphere,
+        texture::SolidColor,
+        util::{color::Color, seed_random, vec::{Point3, Vec3}},
+    };
+
+    // The far side of the sphere lies beyond the ray interval, so only one surface counts
+    let alpha = 0.3;
+    let mask = Rc::new(SolidColor::new(Color::from(alpha)));
+    let mat = Rc::new(Cutout::new(Rc::new(Lambertian::default()), mask));
+    let sphere: Rc<dyn Hittable> = Rc::new(Sphere::new(Point3::new(0, 0, -101), 100, mat));
+    let bvh = BvhNode::new(&mut vec![sphere], 0, 1);
+
+    seed_random(5);
+    let r = Ray::new(Point3::new(0, 0, 0), Vec3::new(0, 0, -1));
+    let mut rec = HitRecord::default();
+    let n = 10000;
+    let hits = (0..n)
+        .filter(|_| bvh.hit(&r, Interval::new(0, 2), &mut rec))
+        .count();
+    let rate = hits as f64 / n as f64;
+    assert!((rate - alpha).abs() < 0.02, "{}", rate);
+}
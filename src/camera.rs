@@ -1,26 +1,116 @@
-use rand::random;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use crate::{
+    aov::{Aov, AovBuffers, AovSample},
+    denoise::Denoiser,
+    environment::{Environment, Gradient},
+    film::Film,
     hittable::{HitRecord, Hittable},
+    light::{power_heuristic, LightList},
+    light_sampler::{LightSampler, LightSampling},
+    medium::Fog,
+    progress::{CancelToken, Progress, ProgressObserver},
+    tile::{CropOutput, CropWindow, Rect, TileScheduler},
     util::{
+        random_f64, random_range, seed_random,
         color::{print_color, Color},
+        colorspace::{ColorSpace, Mat3},
+        filter::Filter,
+        image::Image,
         interval::Interval,
         ray::Ray,
+        spectrum::{SampledWavelengths, RGB_WAVELENGTHS},
+        tonemap::DisplayTransform,
         vec::{Point3, Vec3},
     },
 };
 
+/// What a render accumulates over its progressive passes, all covering the film region
+struct PassState {
+    film: Film,
+    aovs: AovBuffers,
+    progress: Progress,
+    start: Instant,
+}
+
 pub struct Camera {
     pub aspect_ratio: f64,
     pub image_width: i32,
+    /// Sample budget per pixel, the maximum when adaptive sampling is on
     pub samples_per_pixel: i32,
+    /// Samples every pixel takes before it may be considered converged
+    pub min_samples_per_pixel: i32,
+    /// Relative error at which a pixel stops sampling, zero disables adaptive sampling
+    pub noise_threshold: f64,
+    /// Samples per pixel added in each progressive pass, zero renders in a single pass
+    pub pass_samples: i32,
+    /// Where the image so far is written between progressive passes, see `Image::save`
+    pub snapshot_path: Option<PathBuf>,
+    /// Minimum time between two snapshots, zero writes one after every pass
+    pub snapshot_interval: Duration,
+    /// Told about every finished tile, see `ProgressBar` for a ready made one
+    pub observer: Option<Box<dyn ProgressObserver>>,
+    /// Checked between tiles, raising it ends the render with the image so far
+    pub cancel: CancelToken,
+    /// Seeds the random sequence of every pixel, the same seed reproduces a render exactly
+    pub seed: u64,
+    /// Where the accumulated film is saved between progressive passes, see `resume_image`
+    pub checkpoint_path: Option<PathBuf>,
+    /// Minimum time between two checkpoints, zero writes one after every pass
+    pub checkpoint_interval: Duration,
+    /// Part of the frame to render, everything when `None`
+    pub crop: Option<CropWindow>,
+    pub crop_output: CropOutput,
+    /// Edge length in pixels of the tiles the image is rendered in
+    pub tile_size: usize,
+    /// Auxiliary passes to record next to the beauty image, see `aov_images`. They
+    /// cover only the samples taken in this run, not those loaded from a checkpoint.
+    pub aovs: Vec<Aov>,
+    /// Applied to the finished image, which also records the albedo and normal AOVs
+    /// it is guided by
+    pub denoiser: Option<Denoiser>,
+    /// Exposure and tone mapping applied when writing 8 bit output, `render_image`
+    /// itself returns linear radiance
+    pub display: DisplayTransform,
+    /// Linear space the scene's colors are given in and light is computed in
+    pub working_space: ColorSpace,
+    /// Primaries of the rendered image, AOVs stay in the working space
+    pub output_space: ColorSpace,
+    /// Trace every camera sample at three wavelengths instead of in RGB. Colors are
+    /// upsampled to spectra and the results projected back through the CIE observer.
+    pub spectral: bool,
     pub max_depth: i32,
+    /// Shutter interval, kept within the [0, 1] over which moving objects move as
+    /// their bounding boxes only cover that interval
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+    pub fog: Option<Fog>,
+    pub environment: Rc<dyn Environment>,
+    pub lights: LightList,
+    pub light_sampling: LightSampling,
+    pub filter: Filter,
     image_height: i32,
     center: Point3,
     pixel_origin: Point3,
     pixel_delta_u: Vec3,
     pixel_delta_v: Vec3,
-    pixel_samples_scale: f64,
+    light_sampler: Option<Box<dyn LightSampler>>,
+    sample_heatmap: Option<Image>,
+    aov_images: Vec<(Aov, Image)>,
+    region: Rect,
+    /// Pixels sampled into the film, the render region grown by the reach of the
+    /// filter so pixels on the edge of a crop get the splats from outside it
+    film_region: Rect,
+    /// Part of the shutter interval within [0, 1], which ray times are drawn from
+    shutter: Interval,
+    to_srgb: Mat3,
+    from_srgb: Mat3,
 }
 
 impl Default for Camera {
@@ -29,13 +119,46 @@ impl Default for Camera {
             aspect_ratio: 1.0,
             image_width: 100,
             samples_per_pixel: 10,
+            min_samples_per_pixel: 16,
+            noise_threshold: 0.0,
+            pass_samples: 0,
+            snapshot_path: None,
+            snapshot_interval: Duration::ZERO,
+            observer: None,
+            cancel: CancelToken::new(),
+            seed: 0,
+            checkpoint_path: None,
+            checkpoint_interval: Duration::ZERO,
+            crop: None,
+            crop_output: CropOutput::default(),
+            tile_size: 16,
+            aovs: Vec::new(),
+            denoiser: None,
+            display: DisplayTransform::default(),
+            working_space: ColorSpace::default(),
+            output_space: ColorSpace::default(),
+            spectral: false,
             image_height: Default::default(),
             max_depth: 10,
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+            fog: None,
+            environment: Rc::new(Gradient::default()),
+            lights: LightList::new(),
+            light_sampling: LightSampling::default(),
+            filter: Filter::default(),
             center: Default::default(),
             pixel_origin: Default::default(),
             pixel_delta_u: Default::default(),
             pixel_delta_v: Default::default(),
-            pixel_samples_scale: Default::default(),
+            light_sampler: None,
+            sample_heatmap: None,
+            aov_images: Vec::new(),
+            region: Rect::new(0, 0, 0, 0),
+            film_region: Rect::new(0, 0, 0, 0),
+            shutter: Interval::new(0, 1),
+            to_srgb: Mat3::identity(),
+            from_srgb: Mat3::identity(),
         }
     }
 }
@@ -46,36 +169,271 @@ impl Camera {
     }
 
     pub fn render(&mut self, world: &dyn Hittable) {
-        self.initialize();
-
-        println!("P3\n{}\n{}\n255", self.image_width, self.image_height);
-        for j in 0..self.image_height {
-            for i in 0..self.image_width {
-                let mut pcol = Color::default();
-                for _sample in 0..self.samples_per_pixel {
-                    let r = self.get_ray(i, j);
-                    pcol += self.ray_color(&r, self.max_depth, world);
+        let img = self.render_image(world);
+        print_image(&img.to_display(&self.display));
+    }
+
+    /// Like `render`, continuing from a checkpoint, see `resume_image`
+    pub fn resume(&mut self, world: &dyn Hittable, checkpoint: &Path) -> io::Result<()> {
+        let img = self.resume_image(world, checkpoint)?;
+        print_image(&img.to_display(&self.display));
+        return Ok(());
+    }
+
+    pub fn _render_quiet(&mut self, world: &dyn Hittable) {
+        self.render_image(world);
+    }
+
+    /// Renders the scene into a linear radiance image, reconstructing pixels from
+    /// their samples with `filter`
+    pub fn render_image(&mut self, world: &dyn Hittable) -> Image {
+        self.initialize(world);
+
+        let film = Film::new(self.film_region.width(), self.film_region.height(), self.filter);
+        return self.render_film(film, 0, world);
+    }
+
+    /// Continues a render from a checkpoint written by an earlier run with the same
+    /// scene, camera settings and `seed`. The result is identical to rendering
+    /// without interruption.
+    pub fn resume_image(&mut self, world: &dyn Hittable, checkpoint: &Path) -> io::Result<Image> {
+        self.initialize(world);
+
+        let mut film = Film::new(self.film_region.width(), self.film_region.height(), self.filter);
+        let (seed, passes) = film.read_checkpoint(&mut BufReader::new(File::open(checkpoint)?))?;
+        if seed != self.seed {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("checkpoint was rendered with seed {}, not {}", seed, self.seed),
+            ));
+        }
+        return Ok(self.render_film(film, passes, world));
+    }
+
+    /// Renders progressive passes into `film`, which already holds `passes_done` passes,
+    /// until every pixel is converged or out of budget
+    fn render_film(&mut self, film: Film, passes_done: u32, world: &dyn Hittable) -> Image {
+        let pass_samples = if self.pass_samples > 0 {
+            self.pass_samples
+        } else {
+            self.samples_per_pixel
+        };
+        let planned = ((self.samples_per_pixel + pass_samples - 1) / pass_samples.max(1)).max(0) as u32;
+        let mut wanted = self.aovs.clone();
+        if self.denoiser.is_some() {
+            wanted.extend([Aov::Albedo, Aov::Normal]);
+        }
+        let start = Instant::now();
+        let mut state = PassState {
+            film: film,
+            aovs: AovBuffers::new(self.film_region.width(), self.film_region.height(), &wanted),
+            progress: Progress {
+                pass: 0,
+                passes: planned.saturating_sub(passes_done),
+                tiles_done: 0,
+                tiles_total: TileScheduler::new(self.film_region, self.tile_size).len(),
+                samples: 0,
+                elapsed: Duration::ZERO,
+            },
+            start: start,
+        };
+        let mut last_snapshot = start;
+        let mut last_checkpoint = start;
+
+        let mut active = true;
+        while active {
+            state.progress.pass += 1;
+            let pass = passes_done + state.progress.pass;
+            active = match self.render_pass(&mut state, pass, pass_samples, world) {
+                Some(active) => active,
+                None => break,
+            };
+
+            if let Some(path) = &self.snapshot_path {
+                if active && last_snapshot.elapsed() >= self.snapshot_interval {
+                    if let Err(e) = self.save_snapshot(&state.film, path) {
+                        eprintln!("failed to write snapshot {}: {}", path.display(), e);
+                    }
+                    last_snapshot = Instant::now();
+                }
+            }
+
+            if let Some(path) = &self.checkpoint_path {
+                if !active || last_checkpoint.elapsed() >= self.checkpoint_interval {
+                    if let Err(e) = self.save_checkpoint(&state.film, pass, path) {
+                        eprintln!("failed to write checkpoint {}: {}", path.display(), e);
+                    }
+                    last_checkpoint = Instant::now();
                 }
-                print_color(&(pcol * self.pixel_samples_scale));
             }
         }
+
+        let PassState { film, aovs, mut progress, .. } = state;
+        if let Some(observer) = &mut self.observer {
+            if !self.cancel.is_cancelled() {
+                // Adaptive sampling may have converged before the last planned pass
+                progress.passes = progress.pass;
+                progress.tiles_done = progress.tiles_total;
+            }
+            progress.elapsed = start.elapsed();
+            observer.on_finish(&progress);
+        }
+        self.sample_heatmap = Some(self.frame(film.sample_heatmap(self.samples_per_pixel as u32)));
+        self.aov_images = aovs
+            .images()
+            .into_iter()
+            .map(|(aov, img)| (aov, self.frame(img)))
+            .collect();
+
+        let img = self.frame(film.to_image());
+        let img = match &self.denoiser {
+            Some(denoiser) => denoiser.denoise(&img, self.aov_image(Aov::Albedo), self.aov_image(Aov::Normal)),
+            None => img,
+        };
+        return self.to_output_space(img);
     }
 
-    pub fn _render_quiet(&mut self, world: &dyn Hittable) {
-        self.initialize();
-
-        for j in 0..self.image_height {
-            for i in 0..self.image_width {
-                let mut pcol = Color::default();
-                for _sample in 0..self.samples_per_pixel {
-                    let r = self.get_ray(i, j);
-                    pcol += self.ray_color(&r, self.max_depth, world);
+    fn to_output_space(&self, img: Image) -> Image {
+        if self.working_space == self.output_space {
+            return img;
+        }
+        let m = self.working_space.conversion_to(self.output_space);
+        return img.map(|c| m.apply(c));
+    }
+
+    /// Places the render region of an image of the film into the output selected by
+    /// `crop_output`
+    fn frame(&self, film_img: Image) -> Image {
+        if self.crop.is_none() {
+            return film_img;
+        }
+        let (w, h, x0, y0) = match self.crop_output {
+            CropOutput::Cropped => (self.region.width(), self.region.height(), 0, 0),
+            CropOutput::FullFrame => (self.image_width as usize, self.image_height as usize, self.region.x0, self.region.y0),
+        };
+        let (dx, dy) = (self.region.x0 - self.film_region.x0, self.region.y0 - self.film_region.y0);
+        let mut img = Image::new(w, h);
+        for j in 0..self.region.height() {
+            for i in 0..self.region.width() {
+                img.set(x0 + i, y0 + j, film_img.get(dx + i, dy + j));
+            }
+        }
+        return img;
+    }
+
+    /// Snapshots in 8 bit formats go through `display`, float formats stay linear
+    fn save_snapshot(&self, film: &Film, path: &Path) -> io::Result<()> {
+        let img = self.to_output_space(self.frame(film.to_image()));
+        let is_ppm = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("ppm"));
+        if is_ppm {
+            return img.to_display(&self.display).save(path);
+        }
+        return img.save(path);
+    }
+
+    /// Writes next to `path` first so a crash mid write leaves the previous checkpoint intact
+    fn save_checkpoint(&self, film: &Film, passes: u32, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        film.write_checkpoint(&mut writer, self.seed, passes)?;
+        writer.flush()?;
+        drop(writer);
+        return fs::rename(&tmp, path);
+    }
+
+    /// Adds up to `pass_samples` samples to every pixel that is neither converged nor
+    /// out of budget, returning whether any pixel still needs samples afterwards, or
+    /// `None` if the render was cancelled before the pass finished
+    fn render_pass(&mut self, state: &mut PassState, pass: u32, pass_samples: i32, world: &dyn Hittable) -> Option<bool> {
+        let PassState { film, aovs, progress, start } = state;
+        // The film only covers the film region, pixel (i, j) of the frame is
+        // pixel (i - x0, j - y0) of the film
+        let region = self.film_region;
+        let scheduler = TileScheduler::new(region, self.tile_size);
+        let mut active = false;
+        for (t, tile) in scheduler.tiles().enumerate() {
+            if self.cancel.is_cancelled() {
+                return None;
+            }
+            for j in tile.y0..tile.y1 {
+                for i in tile.x0..tile.x1 {
+                    let (fi, fj) = (i - region.x0, j - region.y0);
+                    // Every pixel of every pass draws from its own random sequence, so a
+                    // render resumed after any pass continues exactly where it left off
+                    seed_random(self.pixel_seed(pass, i, j));
+                    for _ in 0..pass_samples {
+                        if self.pixel_done(film, fi, fj) {
+                            break;
+                        }
+                        let offset = self.sample_square();
+                        let x = i as f64 + offset.x();
+                        let y = j as f64 + offset.y();
+                        let r = self.get_ray(x, y);
+                        let mut aov = AovSample::default();
+                        let c = if self.spectral {
+                            let wl = SampledWavelengths::sample(random_f64());
+                            let c = self.ray_color(&r, self.max_depth, world, Some(&wl), &mut aov);
+                            aov.direct = self.spectral_to_rgb(&wl, aov.direct);
+                            aov.indirect = self.spectral_to_rgb(&wl, aov.indirect);
+                            self.spectral_to_rgb(&wl, c)
+                        } else {
+                            self.ray_color(&r, self.max_depth, world, None, &mut aov)
+                        };
+                        film.add_sample(x - region.x0 as f64, y - region.y0 as f64, c);
+                        film.record_sample(fi, fj, c);
+                        if !aovs.is_empty() {
+                            aovs.add_sample(fi, fj, &aov);
+                        }
+                        progress.samples += 1;
+                    }
+                    active |= !self.pixel_done(film, fi, fj);
                 }
             }
+
+            if let Some(observer) = &mut self.observer {
+                progress.tiles_done = t + 1;
+                progress.elapsed = start.elapsed();
+                observer.on_progress(progress);
+            }
         }
+        return Some(active);
+    }
+
+    fn pixel_seed(&self, pass: u32, i: usize, j: usize) -> u64 {
+        let pixel = (j * self.image_width as usize + i) as u64;
+        return mix_bits(self.seed ^ mix_bits(((pass as u64) << 40) ^ pixel));
+    }
+
+    /// AOVs of the last render, in the order they were requested
+    pub fn aov_images(&self) -> &[(Aov, Image)] {
+        return &self.aov_images;
     }
 
-    fn initialize(&mut self) {
+    pub fn aov_image(&self, aov: Aov) -> Option<&Image> {
+        return self.aov_images.iter().find(|(a, _)| *a == aov).map(|(_, img)| img);
+    }
+
+    /// Sample counts of the last render as a false color image, for checking where
+    /// adaptive sampling spent its budget
+    pub fn sample_heatmap(&self) -> Option<&Image> {
+        return self.sample_heatmap.as_ref();
+    }
+
+    /// Whether pixel `(i, j)` has used its budget or converged. Convergence is only
+    /// tested every few samples so a lucky streak can't end a pixel early.
+    fn pixel_done(&self, film: &Film, i: usize, j: usize) -> bool {
+        let taken = film.sample_count(i, j) as i32;
+        if taken >= self.samples_per_pixel {
+            return true;
+        }
+        if self.noise_threshold <= 0.0 || taken < self.min_samples_per_pixel.max(2) || taken % 8 != 0 {
+            return false;
+        }
+        return film.relative_error(i, j) < self.noise_threshold;
+    }
+
+    fn initialize(&mut self, world: &dyn Hittable) {
         self.image_height = (self.image_width as f64 / self.aspect_ratio as f64) as i32;
         self.image_height = if self.image_height < 1 {
             1
@@ -83,7 +441,19 @@ impl Camera {
             self.image_height
         };
 
-        self.pixel_samples_scale = 1.0 / self.samples_per_pixel as f64;
+        let frame = Rect::new(0, 0, self.image_width as usize, self.image_height as usize);
+        self.region = match &self.crop {
+            Some(crop) => crop.resolve(frame.width(), frame.height()),
+            None => frame,
+        };
+        let reach = (self.filter.radius() - 0.5).ceil().max(0.0) as usize;
+        self.film_region = self.region.expand(reach, &frame);
+        // Clamped once rather than per ray, which would pile times up on the ends
+        self.shutter = Interval::new(self.shutter_open.clamp(0.0, 1.0), self.shutter_close.clamp(0.0, 1.0));
+
+        // Spectral upsampling works on linear sRGB
+        self.to_srgb = self.working_space.conversion_to(ColorSpace::LinearSrgb);
+        self.from_srgb = ColorSpace::LinearSrgb.conversion_to(self.working_space);
 
         let focal_length = 1.0;
         let view_height: f64 = 2.0;
@@ -97,39 +467,510 @@ impl Camera {
 
         let view_upper_left = self.center - Vec3::new(0, 0, focal_length) - view_u / 2 - view_v / 2;
         self.pixel_origin = view_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
+
+        self.light_sampler = if self.lights.is_empty() {
+            None
+        } else {
+            Some(self.light_sampling.build(&self.lights, &world.bounding_box()))
+        };
     }
 
-    fn get_ray(&self, i: i32, j: i32) -> Ray {
-        let offset = self.sample_square();
-        let px_sample = self.pixel_origin
-            + ((i as f64 + offset.x()) * self.pixel_delta_u)
-            + ((j as f64 + offset.y()) * self.pixel_delta_v);
+    /// Ray through continuous pixel coordinates `(x, y)`, with pixel centers at whole numbers
+    fn get_ray(&self, x: f64, y: f64) -> Ray {
+        let px_sample = self.pixel_origin + (x * self.pixel_delta_u) + (y * self.pixel_delta_v);
         let ray_origin = self.center;
         let ray_dir = px_sample - ray_origin;
+        let ray_time = random_range(self.shutter.min, self.shutter.max);
 
-        return Ray::new(ray_origin, ray_dir);
+        return Ray::new_timed(ray_origin, ray_dir, ray_time);
     }
 
     #[inline]
     fn sample_square(&self) -> Vec3 {
-        return Vec3::new(random::<f64>() - 0.5, random::<f64>() - 0.5, 0);
+        return Vec3::new(random_f64() - 0.5, random_f64() - 0.5, 0);
     }
 
-    fn ray_color(&self, r: &Ray, depth: i32, world: &dyn Hittable) -> Color {
-        if depth <= 0 {
-            return Color::default();
-        }
-        let mut rec = HitRecord::default();
-        if world.hit(r, Interval::new(0, f64::INFINITY), &mut rec) {
+    /// Radiance arriving along `r`, also filling in `aov` from the first hit and
+    /// splitting the light it receives into direct and indirect. With wavelengths
+    /// given the result holds the radiance at each of them instead of RGB.
+    fn ray_color(
+        &self,
+        r: &Ray,
+        depth: i32,
+        world: &dyn Hittable,
+        wl: Option<&SampledWavelengths>,
+        aov: &mut AovSample,
+    ) -> Color {
+        let mut radiance = Color::default();
+        let mut throughput = Color::new(1, 1, 1);
+        let mut ray = *r;
+        // Density of the scatter that produced `ray`, zero for camera rays and
+        // discrete reflections whose emission can't be reached by light sampling
+        let mut scatter_pdf = 0.0;
+        let mut prev_p = ray.origin();
+        let mut prev_n = Vec3::default();
+
+        for bounce in 0..depth {
+            let mut rec = HitRecord::default();
+            let mut has_hit = world.hit(&ray, Interval::new(0, f64::INFINITY), &mut rec);
+
+            if let Some(fog) = &self.fog {
+                let t_max = if has_hit { rec.t } else { f64::INFINITY };
+                if let Some(fog_rec) = fog.scatter_before(&ray, t_max) {
+                    rec = fog_rec;
+                    has_hit = true;
+                }
+            }
+
+            if !has_hit {
+                let dir = ray.direction().to_normal();
+                let weight = if scatter_pdf > 0.0 {
+                    power_heuristic(scatter_pdf, self.environment.pdf(dir))
+                } else {
+                    1.0
+                };
+                let contribution = weight * throughput * self.to_radiance(wl, self.environment.value(dir));
+                radiance += contribution;
+                aov.add_emission(bounce, contribution);
+                break;
+            }
+
+            if bounce == 0 {
+                aov.depth = rec.t * ray.direction().length();
+                aov.normal = rec.normal;
+                aov.albedo = rec.mat.albedo(&rec);
+                aov.position = rec.p;
+                aov.object_id = rec.object_id;
+                aov.material_id = rec.material_id;
+            }
+
+            let emitted = rec.mat.emitted(&ray, &rec);
+            if !emitted.near_zero() {
+                let weight = match (&rec.light, &self.light_sampler) {
+                    (Some(light), Some(sampler)) if scatter_pdf > 0.0 => {
+                        let dir = ray.direction().to_normal();
+                        let light_pdf = sampler.pmf(prev_p, prev_n, light) * light.pdf_li(prev_p, dir);
+                        power_heuristic(scatter_pdf, light_pdf)
+                    }
+                    _ => 1.0,
+                };
+                let contribution = weight * throughput * self.to_radiance(wl, emitted);
+                radiance += contribution;
+                aov.add_emission(bounce, contribution);
+            }
+
+            if rec.mat.is_dispersive() && ray.wavelength().is_none() {
+                // The wavelengths part ways here, so the rest of the path follows just one
+                // of them, carrying three times the weight to stay unbiased
+                let (k, lambda, channel) = match wl {
+                    Some(wl) => (0, wl.hero(), None),
+                    None => {
+                        let k = ((3.0 * random_f64()) as usize).min(2);
+                        (k, RGB_WAVELENGTHS[k], Some(k))
+                    }
+                };
+                let mut mask = Color::default();
+                mask[k] = 3.0;
+                throughput *= mask;
+                ray = ray.with_wavelength(Some(lambda)).with_channel(channel);
+            }
+
             let mut scattered = Ray::default();
             let mut attenuation = Color::default();
-            if rec.mat.scatter(r, &rec, &mut attenuation, &mut scattered) {
-                return attenuation * self.ray_color(&scattered, depth-1, world);
+            let pdf = match rec.mat.scatter_with_pdf(&ray, &rec, &mut attenuation, &mut scattered) {
+                Some(pdf) => pdf,
+                None => break,
+            };
+
+            let direct = throughput * self.direct_light(&ray, &rec, world, wl);
+            radiance += direct;
+            if bounce == 0 {
+                aov.direct += direct;
+            } else {
+                aov.indirect += direct;
+            }
+
+            scatter_pdf = pdf;
+            prev_p = rec.p;
+            prev_n = rec.normal;
+            throughput *= self.to_reflectance(wl, attenuation);
+            ray = scattered.with_wavelength(ray.wavelength()).with_channel(ray.channel());
+        }
+
+        return radiance;
+    }
+
+    /// Next event estimation at `rec`: one light picked by the light sampler plus
+    /// one sample of the environment, each weighted against scattering into it
+    fn direct_light(&self, r: &Ray, rec: &HitRecord, world: &dyn Hittable, wl: Option<&SampledWavelengths>) -> Color {
+        let mut total = Color::default();
+
+        let sampled = match &self.light_sampler {
+            Some(sampler) => sampler.sample(rec.p, rec.normal, random_f64()),
+            None => None,
+        };
+        if let Some(sampled) = sampled {
+            if let Some(ls) = sampled.light.sample_li(rec.p) {
+                let light_pdf = sampled.pmf * ls.pdf;
+                let f = rec.mat.eval(r, rec, ls.wi);
+                if light_pdf > 0.0 && !f.near_zero() && self.unoccluded(r, rec, ls.wi, ls.dist, world) {
+                    let weight = if sampled.light.is_delta() {
+                        1.0
+                    } else {
+                        power_heuristic(light_pdf, rec.mat.pdf(r, rec, ls.wi))
+                    };
+                    let li = self.to_reflectance(wl, f) * self.to_radiance(wl, ls.li);
+                    total += (weight * self.transmittance(ls.dist) / light_pdf) * li;
+                }
+            }
+        }
+
+        let env = self.environment.sample();
+        if env.pdf > 0.0 {
+            let wi = env.dir.to_normal();
+            let f = rec.mat.eval(r, rec, wi);
+            if !f.near_zero() && self.unoccluded(r, rec, wi, f64::INFINITY, world) {
+                let weight = power_heuristic(env.pdf, rec.mat.pdf(r, rec, wi));
+                let li = self.to_reflectance(wl, f) * self.to_radiance(wl, env.value);
+                total += (weight * self.transmittance(f64::INFINITY) / env.pdf) * li;
+            }
+        }
+
+        return total;
+    }
+
+    /// A reflectance or BSDF value in the working space, as values at the sampled
+    /// wavelengths in spectral mode
+    fn to_reflectance(&self, wl: Option<&SampledWavelengths>, c: Color) -> Color {
+        return match wl {
+            Some(wl) => wl.reflectance(self.to_srgb.apply(c)),
+            None => c,
+        };
+    }
+
+    /// Emitted radiance in the working space, as values at the sampled wavelengths in
+    /// spectral mode
+    fn to_radiance(&self, wl: Option<&SampledWavelengths>, c: Color) -> Color {
+        return match wl {
+            Some(wl) => wl.illuminant(self.to_srgb.apply(c)),
+            None => c,
+        };
+    }
+
+    fn spectral_to_rgb(&self, wl: &SampledWavelengths, values: Color) -> Color {
+        return self.from_srgb.apply(wl.to_rgb(values));
+    }
+
+    fn unoccluded(&self, r: &Ray, rec: &HitRecord, wi: Vec3, dist: f64, world: &dyn Hittable) -> bool {
+        let shadow_ray = Ray::new_timed(rec.p, wi, r.time());
+        let mut shadow_rec = HitRecord::default();
+        return !world.hit(&shadow_ray, Interval::new(0.001, dist - 0.001), &mut shadow_rec);
+    }
+
+    fn transmittance(&self, dist: f64) -> f64 {
+        return match &self.fog {
+            Some(fog) => fog.transmittance(dist),
+            None => 1.0,
+        };
+    }
+}
+
+fn print_image(img: &Image) {
+    println!("P3\n{}\n{}\n255", img.width(), img.height());
+    for c in img.data() {
+        print_color(c);
+    }
+}
+
+/// SplitMix64 finalizer, scrambles nearby integers into unrelated seeds
+fn mix_bits(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    return z ^ (z >> 31);
+}
+
+#[cfg(test)]
+struct CancelAfterFirstPass(CancelToken);
+
+#[cfg(test)]
+impl ProgressObserver for CancelAfterFirstPass {
+    fn on_progress(&mut self, progress: &Progress) {
+        if progress.pass == 1 && progress.tiles_done == progress.tiles_total {
+            self.0.cancel();
+        }
+    }
+}
+
+/// Average color of a render, for scenes whose expected brightness is known
+#[cfg(test)]
+fn mean_color(cam: &mut Camera, world: &dyn Hittable) -> Color {
+    let img = cam.render_image(world);
+    let mut sum = Color::default();
+    for j in 0..img.height() {
+        for i in 0..img.width() {
+            sum += img.get(i, j);
+        }
+    }
+    return sum / (img.width() * img.height()) as f64;
+}
+
+#[test]
+fn resumed_render_matches_uninterrupted() {
+    use crate::{hittable::HittableList, material::Lambertian, sphere::Sphere};
+
+    let mut world = HittableList::new();
+    let mat = Rc::new(Lambertian::new(Color::new(0.5, 0.4, 0.3)));
+    world.add(Rc::new(Sphere::new(Point3::new(0, 0, -1), 0.5, mat.clone())));
+    world.add(Rc::new(Sphere::new(Point3::new(0, -100.5, -1), 100.0, mat)));
+
+    let camera = || {
+        let mut cam = Camera::new();
+        cam.image_width = 8;
+        cam.samples_per_pixel = 6;
+        cam.pass_samples = 2;
+        cam.filter = Filter::gaussian(1.5, 0.5);
+        cam.seed = 1234;
+        return cam;
+    };
+    let expected = camera().render_image(&world);
+
+    let path = std::env::temp_dir().join(format!("raytracer-resume-{}.ckpt", std::process::id()));
+    let mut interrupted = camera();
+    interrupted.checkpoint_path = Some(path.clone());
+    interrupted.observer = Some(Box::new(CancelAfterFirstPass(interrupted.cancel.clone())));
+    assert_ne!(interrupted.render_image(&world), expected);
+
+    let resumed = camera().resume_image(&world, &path).unwrap();
+    let mut other_seed = camera();
+    other_seed.seed = 99;
+    assert!(other_seed.resume_image(&world, &path).is_err());
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(resumed, expected);
+}
+
+#[test]
+fn crop_renders_matching_region() {
+    use crate::{hittable::HittableList, material::Lambertian, sphere::Sphere};
+
+    let mut world = HittableList::new();
+    let mat = Rc::new(Lambertian::new(Color::new(0.5, 0.4, 0.3)));
+    world.add(Rc::new(Sphere::new(Point3::new(0, 0, -1), 0.5, mat)));
+
+    let camera = || {
+        let mut cam = Camera::new();
+        cam.image_width = 12;
+        cam.samples_per_pixel = 2;
+        cam.tile_size = 5;
+        // Wide enough that edge pixels of the crop get splats from outside it
+        cam.filter = Filter::gaussian(1.5, 0.5);
+        return cam;
+    };
+    let full = camera().render_image(&world);
+
+    let mut cam = camera();
+    cam.crop = Some(CropWindow::Pixels(Rect::new(3, 4, 9, 7)));
+    let framed = cam.render_image(&world);
+    cam.crop_output = CropOutput::Cropped;
+    let cropped = cam.render_image(&world);
+
+    // Splats are summed in another tile order, so only rounding may differ
+    let close = |a: Color, b: Color| (a - b).length() <= 1e-12 * b.length().max(1.0);
+    assert_eq!((cropped.width(), cropped.height()), (6, 3));
+    for j in 0..full.height() {
+        for i in 0..full.width() {
+            if (3..9).contains(&i) && (4..7).contains(&j) {
+                assert!(close(framed.get(i, j), full.get(i, j)), "{} {}", i, j);
+                assert!(close(cropped.get(i - 3, j - 4), full.get(i, j)), "{} {}", i, j);
+            } else {
+                assert_eq!(framed.get(i, j), Color::default());
             }
-            return Color::default();
         }
+    }
+}
+
+#[test]
+fn direct_and_indirect_add_up_to_beauty() {
+    use crate::{hittable::HittableList, material::Lambertian, sphere::Sphere};
+
+    let mut world = HittableList::new();
+    let mat = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    world.add(Rc::new(Sphere::new(Point3::new(0, -100.5, -1), 100.0, mat.clone())));
+    world.add(Rc::new(Sphere::new(Point3::new(0, 0, -1), 0.5, mat)));
+
+    let mut cam = Camera::new();
+    cam.image_width = 6;
+    cam.samples_per_pixel = 4;
+    cam.aovs = vec![Aov::Direct, Aov::Indirect, Aov::Depth, Aov::Normal];
+    let beauty = cam.render_image(&world);
+
+    let j = beauty.height() - 1;
+    let mut indirect_sum = Color::default();
+    for i in 0..beauty.width() {
+        let direct = cam.aov_image(Aov::Direct).unwrap().get(i, j);
+        let indirect = cam.aov_image(Aov::Indirect).unwrap().get(i, j);
+        assert!((beauty.get(i, j) - direct - indirect).near_zero());
+        indirect_sum += indirect;
+        assert!(cam.aov_image(Aov::Depth).unwrap().get(i, j).x() > 0.5);
+        assert!(cam.aov_image(Aov::Normal).unwrap().get(i, j).y() > 0.9);
+    }
+    assert!(!indirect_sum.near_zero());
+    assert!(cam.aov_image(Aov::Albedo).is_none());
+}
+
+#[test]
+fn spectral_render_agrees_with_rgb() {
+    use crate::{hittable::HittableList, material::Lambertian, sphere::Sphere};
+
+    let mut world = HittableList::new();
+    let mat = Rc::new(Lambertian::new(Color::new(0.7, 0.4, 0.2)));
+    world.add(Rc::new(Sphere::new(Point3::new(0, -100.5, -1), 100.0, mat.clone())));
+    world.add(Rc::new(Sphere::new(Point3::new(0, 0, -1), 0.5, mat)));
+
+    let mean = |spectral: bool| {
+        let mut cam = Camera::new();
+        cam.image_width = 4;
+        cam.samples_per_pixel = 64;
+        cam.max_depth = 8;
+        cam.spectral = spectral;
+        return mean_color(&mut cam, &world);
+    };
+    let rgb = mean(false);
+    let spectral = mean(true);
+    assert!((rgb - spectral).length() < 0.05 * rgb.length(), "{:?} != {:?}", rgb, spectral);
+}
+
+#[test]
+fn dispersive_glass_keeps_energy() {
+    use crate::{
+        environment::ConstantEnvironment, hittable::HittableList, material::Dielectric, sphere::Sphere,
+        util::ior::Ior,
+    };
+
+    let mut world = HittableList::new();
+    world.add(Rc::new(Sphere::new(Point3::new(0, 0, -1), 0.5, Rc::new(Dielectric::new(Ior::DIAMOND)))));
+
+    // Inside a uniform environment a clear object is invisible on average, however
+    // its paths are split into wavelengths
+    let sky = Color::new(0.6, 0.5, 0.4);
+    for spectral in [false, true] {
+        let mut cam = Camera::new();
+        cam.image_width = 4;
+        cam.samples_per_pixel = 128;
+        cam.max_depth = 16;
+        cam.environment = Rc::new(ConstantEnvironment::new(sky));
+        cam.spectral = spectral;
+        let mean = mean_color(&mut cam, &world);
+        assert!((mean - sky).length() < 0.05, "spectral {}: {:?}", spectral, mean);
+    }
+}
+
+#[test]
+fn soap_bubble_splits_light_without_losing_it() {
+    use crate::{
+        environment::ConstantEnvironment,
+        hittable::HittableList,
+        material::{Dielectric, ThinFilm},
+        sphere::Sphere,
+        util::ior::Ior,
+    };
+
+    let bubble = Dielectric::new(Ior::Constant(1.0)).with_coating(ThinFilm::new(1.33, 380.0));
+    let mut world = HittableList::new();
+    world.add(Rc::new(Sphere::new(Point3::new(0, 0, -1), 0.5, Rc::new(bubble))));
+
+    let sky = Color::new(0.5, 0.5, 0.5);
+    let mut cam = Camera::new();
+    cam.image_width = 4;
+    cam.samples_per_pixel = 64;
+    cam.max_depth = 16;
+    cam.environment = Rc::new(ConstantEnvironment::new(sky));
+    let mean = mean_color(&mut cam, &world);
+    assert!((mean - sky).length() < 0.05, "{:?}", mean);
+}
+
+#[test]
+fn mixed_and_layered_materials_keep_energy() {
+    use crate::{
+        environment::ConstantEnvironment,
+        hittable::HittableList,
+        material::{Lambertian, Layered, Material, Metal, Mix},
+        sphere::Sphere,
+        texture::CheckerTexture,
+    };
+
+    let sky = Color::new(0.5, 0.5, 0.5);
+    let mean = |mat: Rc<dyn Material>| {
+        let mut world = HittableList::new();
+        world.add(Rc::new(Sphere::new(Point3::new(0, 0, -1), 0.5, mat)));
+        let mut cam = Camera::new();
+        cam.image_width = 4;
+        cam.samples_per_pixel = 64;
+        cam.max_depth = 16;
+        cam.environment = Rc::new(ConstantEnvironment::new(sky));
+        return mean_color(&mut cam, &world);
+    };
+
+    // White materials in a uniform environment give back exactly the environment
+    let white: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(1, 1, 1)));
+    let mirror: Rc<dyn Material> = Rc::new(Metal::new(Color::new(1, 1, 1)));
+    let checker = Rc::new(CheckerTexture::from_colors(0.2, Color::from(0.2), Color::from(0.9)));
+    let mix = mean(Rc::new(Mix::textured(white.clone(), mirror, checker)));
+    assert!((mix - sky).length() < 0.03, "{:?}", mix);
+
+    // The coat reflects what it keeps from the base, apart from what it loses inside
+    let coated = mean(Rc::new(Layered::new(white, 1.5)));
+    assert!(coated.x() < sky.x() + 0.03 && coated.x() > 0.8 * sky.x(), "{:?}", coated);
+}
+
+#[test]
+fn subsurface_walk_returns_scattered_light() {
+    use crate::{
+        environment::ConstantEnvironment, hittable::HittableList, material::Lambertian, medium::SubsurfaceMedium,
+        sphere::Sphere,
+    };
+
+    let sky = Color::new(0.5, 0.5, 0.5);
+    let mean = |albedo: f64, spectral: bool| {
+        let ball = Rc::new(Sphere::new(Point3::new(0, 0, -1), 0.5, Rc::new(Lambertian::default())));
+        let mut world = HittableList::new();
+        world.add(Rc::new(SubsurfaceMedium::new(ball, Color::from(albedo), Color::new(0.4, 0.2, 0.05))));
+        let mut cam = Camera::new();
+        cam.image_width = 4;
+        cam.samples_per_pixel = 64;
+        cam.max_depth = 512;
+        cam.environment = Rc::new(ConstantEnvironment::new(sky));
+        cam.spectral = spectral;
+        return mean_color(&mut cam, &world);
+    };
+
+    // Without absorption every walk gets out again
+    for spectral in [false, true] {
+        let clear = mean(1.0, spectral);
+        assert!((clear - sky).length() < 0.05, "spectral {}: {:?}", spectral, clear);
+    }
+    // Blue collides more often over the same path, so it is absorbed more
+    let absorbing = mean(0.9, false);
+    assert!(absorbing.x() > absorbing.z() && absorbing.x() < sky.x(), "{:?}", absorbing);
+}
 
-        let a = 0.5 * (r.direction().to_normal().y() + 1.0);
-        return (1.0 - a) * Color::new(1, 1, 1) + a * Color::new(0.5, 0.7, 1.0);
+#[test]
+fn ray_times_stay_within_motion_interval() {
+    let mut cam = Camera::new();
+    cam.shutter_open = -0.5;
+    cam.shutter_close = 2.0;
+    cam.initialize(&crate::hittable::HittableList::new());
+    seed_random(5);
+    let n = 20000;
+    let times: Vec<f64> = (0..n).map(|_| cam.get_ray(0.0, 0.0).time()).collect();
+    for &t in &times {
+        assert!((0.0..=1.0).contains(&t), "{}", t);
     }
+    // Uniform over [0, 1] rather than bunched up on the ends
+    let mean = times.iter().sum::<f64>() / n as f64;
+    let variance = times.iter().map(|t| (t - mean) * (t - mean)).sum::<f64>() / n as f64;
+    assert!((mean - 0.5).abs() < 0.01, "{}", mean);
+    assert!((variance - 1.0 / 12.0).abs() < 0.003, "{}", variance);
+    let ends = times.iter().filter(|&&t| t == 0.0 || t == 1.0).count();
+    assert_eq!(ends, 0);
 }
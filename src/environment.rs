@@ -0,0 +1,250 @@
+use std::{
+    f64::consts::PI,
+    fmt::Debug,
+    io,
+    path::Path,
+};
+
+use crate::util::{
+    random_f64,
+    color::{luminance, Color},
+    distribution::Distribution2D,
+    image::Image,
+    vec::Vec3,
+};
+
+/// Radiance arriving from infinitely far away, seen by rays that leave the scene
+pub trait Environment {
+    fn value(&self, dir: Vec3) -> Color;
+
+    /// Picks a direction towards the environment for light sampling
+    fn sample(&self) -> EnvironmentSample {
+        let dir = Vec3::random_normal();
+        EnvironmentSample {
+            dir: dir,
+            value: self.value(dir),
+            pdf: 1.0 / (4.0 * PI),
+        }
+    }
+
+    /// Solid angle density with which `sample` returns `dir`
+    fn pdf(&self, _dir: Vec3) -> f64 {
+        return 1.0 / (4.0 * PI);
+    }
+}
+
+impl Debug for dyn Environment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad("Environment")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvironmentSample {
+    pub dir: Vec3,
+    pub value: Color,
+    pub pdf: f64,
+}
+
+/// Vertical blend between two colors, the classic sky backdrop
+#[derive(Debug, Clone, Copy)]
+pub struct Gradient {
+    bottom: Color,
+    top: Color,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConstantEnvironment {
+    color: Color,
+}
+
+/// Equirectangular (latitude-longitude) environment map, importance sampled by luminance
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    image: Image,
+    rotation: f64,
+    intensity: f64,
+    distribution: Distribution2D,
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        Self {
+            bottom: Color::new(1, 1, 1),
+            top: Color::new(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+impl Gradient {
+    pub fn new(bottom: Color, top: Color) -> Self {
+        Self {
+            bottom: bottom,
+            top: top,
+        }
+    }
+}
+
+impl Environment for Gradient {
+    fn value(&self, dir: Vec3) -> Color {
+        let a = 0.5 * (dir.to_normal().y() + 1.0);
+        return (1.0 - a) * self.bottom + a * self.top;
+    }
+}
+
+impl ConstantEnvironment {
+    pub fn new(color: Color) -> Self {
+        Self { color: color }
+    }
+}
+
+impl Environment for ConstantEnvironment {
+    fn value(&self, _dir: Vec3) -> Color {
+        return self.color;
+    }
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Image) -> Self {
+        let (width, height) = (image.width(), image.height());
+        assert!(width > 0 && height > 0, "environment map has no pixels");
+        let mut weights = vec![0.0; width * height];
+        for y in 0..height {
+            // Rows near the poles cover less solid angle
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            for x in 0..width {
+                weights[y * width + x] = luminance(&image.get(x, y)) * sin_theta;
+            }
+        }
+
+        Self {
+            image: image,
+            rotation: 0.0,
+            intensity: 1.0,
+            distribution: Distribution2D::new(&weights, width, height),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        return Ok(Self::new(Image::load(path)?));
+    }
+
+    /// Rotates the map around the vertical axis by `radians`
+    pub fn with_rotation(mut self, radians: f64) -> Self {
+        self.rotation = radians;
+        return self;
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        return self;
+    }
+
+    fn dir_to_uv(&self, dir: Vec3) -> (f64, f64) {
+        let d = rotate_y(dir.to_normal(), -self.rotation);
+        let phi = (-d.z()).atan2(d.x()) + PI;
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        return (phi / (2.0 * PI), theta / PI);
+    }
+
+    fn uv_to_dir(&self, u: f64, v: f64) -> Vec3 {
+        let phi = 2.0 * PI * u;
+        let theta = PI * v;
+        let d = Vec3::new(
+            -theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        );
+        return rotate_y(d, self.rotation);
+    }
+
+    fn lookup(&self, u: f64, v: f64) -> Color {
+        let x = ((u * self.image.width() as f64) as usize).min(self.image.width() - 1);
+        let y = ((v * self.image.height() as f64) as usize).min(self.image.height() - 1);
+        return self.intensity * self.image.get(x, y);
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn value(&self, dir: Vec3) -> Color {
+        let (u, v) = self.dir_to_uv(dir);
+        return self.lookup(u, v);
+    }
+
+    fn sample(&self) -> EnvironmentSample {
+        let ((u, v), pdf_uv) = self.distribution.sample_continuous(random_f64(), random_f64());
+        let sin_theta = (PI * v).sin();
+        let pdf = if sin_theta > 0.0 {
+            pdf_uv / (2.0 * PI * PI * sin_theta)
+        } else {
+            0.0
+        };
+
+        EnvironmentSample {
+            dir: self.uv_to_dir(u, v),
+            value: self.lookup(u, v),
+            pdf: pdf,
+        }
+    }
+
+    fn pdf(&self, dir: Vec3) -> f64 {
+        let (u, v) = self.dir_to_uv(dir);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        return self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta);
+    }
+}
+
+#[inline]
+fn rotate_y(d: Vec3, angle: f64) -> Vec3 {
+    let (s, c) = angle.sin_cos();
+    return Vec3::new(c * d.x() + s * d.z(), d.y(), -s * d.x() + c * d.z());
+}
+
+#[cfg(test)]
+fn test_map() -> EnvironmentMap {
+    let mut img = Image::new(8, 4);
+    img.set(1, 1, Color::new(10, 10, 10));
+    img.set(6, 2, Color::new(0.5, 1.0, 2.0));
+    img.set(3, 3, Color::new(1, 0, 0));
+    return EnvironmentMap::new(img).with_rotation(0.7);
+}
+
+#[test]
+fn environment_map_uv_roundtrip() {
+    let env = test_map();
+    let dir = Vec3::new(0.3, -0.4, 0.8).to_normal();
+    let (u, v) = env.dir_to_uv(dir);
+
+    assert!((env.uv_to_dir(u, v) - dir).length() < 1e-9);
+}
+
+#[test]
+fn environment_map_pdf_integrates_to_one() {
+    let env = test_map();
+    let (nt, np) = (400, 800);
+    let mut integral = 0.0;
+    for i in 0..nt {
+        let theta = PI * (i as f64 + 0.5) / nt as f64;
+        for j in 0..np {
+            let phi = 2.0 * PI * (j as f64 + 0.5) / np as f64;
+            let dir = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+            integral += env.pdf(dir) * theta.sin() * (PI / nt as f64) * (2.0 * PI / np as f64);
+        }
+    }
+
+    assert!((integral - 1.0).abs() < 1e-2, "integral = {}", integral);
+}
+
+#[test]
+fn environment_map_sample_matches_pdf() {
+    let env = test_map();
+    for _ in 0..100 {
+        let s = env.sample();
+        assert!(s.pdf > 0.0);
+        assert!((env.pdf(s.dir) - s.pdf).abs() < 1e-6 * s.pdf);
+        assert_eq!(env.value(s.dir), s.value);
+    }
+}
@@ -0,0 +1,274 @@
+use std::io::{self, BufRead, Write};
+
+use crate::util::{
+    color::{luminance, Color},
+    filter::Filter,
+    image::Image,
+};
+
+/// Framebuffer accumulating filter weighted samples for every pixel.
+///
+/// Checkpoints store a film on disk as a text header line
+/// `CKPT <width> <height> <seed> <passes>` followed by, for every pixel, the
+/// weighted color sum, filter weight and luminance sums as little endian `f64`
+/// and the sample count as a little endian `u32`. Values are stored at full
+/// precision so a resumed render matches an uninterrupted one bit for bit.
+#[derive(Debug, Clone)]
+pub struct Film {
+    width: usize,
+    height: usize,
+    filter: Filter,
+    sum: Vec<Color>,
+    weight: Vec<f64>,
+    stats: Vec<PixelStats>,
+}
+
+/// Running luminance statistics of the samples taken for one pixel
+#[derive(Debug, Clone, Copy, Default)]
+struct PixelStats {
+    count: u32,
+    sum: f64,
+    sum_sq: f64,
+}
+
+impl PixelStats {
+    fn add(&mut self, y: f64) {
+        self.count += 1;
+        self.sum += y;
+        self.sum_sq += y * y;
+    }
+
+    /// Standard error of the mean luminance relative to the mean itself. The mean
+    /// is floored so dark pixels don't need an absurd number of samples.
+    fn relative_error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.count as f64;
+        let mean = self.sum / n;
+        let variance = ((self.sum_sq - n * mean * mean) / (n - 1.0)).max(0.0);
+        return (variance / n).sqrt() / mean.max(1e-2);
+    }
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Filter) -> Self {
+        Self {
+            width: width,
+            height: height,
+            filter: filter,
+            sum: vec![Color::default(); width * height],
+            weight: vec![0.0; width * height],
+            stats: vec![PixelStats::default(); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        return self.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.height;
+    }
+
+    /// Splats a sample taken at continuous pixel coordinates `(x, y)`, where pixel
+    /// `(i, j)` is centered on `(i, j)`, into every pixel within the filter radius
+    pub fn add_sample(&mut self, x: f64, y: f64, c: Color) {
+        let radius = self.filter.radius();
+        let x0 = (x - radius).ceil().max(0.0) as usize;
+        let y0 = (y - radius).ceil().max(0.0) as usize;
+        let x1 = (x + radius).floor().min(self.width as f64 - 1.0);
+        let y1 = (y + radius).floor().min(self.height as f64 - 1.0);
+        if x1 < 0.0 || y1 < 0.0 {
+            return;
+        }
+
+        for j in y0..=(y1 as usize) {
+            for i in x0..=(x1 as usize) {
+                let w = self.filter.eval(i as f64 - x, j as f64 - y);
+                if w == 0.0 {
+                    continue;
+                }
+                let idx = j * self.width + i;
+                self.sum[idx] += w * c;
+                self.weight[idx] += w;
+            }
+        }
+    }
+
+    /// Records a sample taken for pixel `(i, j)` in its convergence statistics,
+    /// separately from the filter splat which may reach neighbouring pixels
+    pub fn record_sample(&mut self, i: usize, j: usize, c: Color) {
+        self.stats[j * self.width + i].add(luminance(&c));
+    }
+
+    pub fn sample_count(&self, i: usize, j: usize) -> u32 {
+        return self.stats[j * self.width + i].count;
+    }
+
+    /// Estimated relative error of pixel `(i, j)`, infinite until it has two samples
+    pub fn relative_error(&self, i: usize, j: usize) -> f64 {
+        return self.stats[j * self.width + i].relative_error();
+    }
+
+    /// Sample counts as a false color image going from black through red and
+    /// yellow to white as pixels approach `max_samples`
+    pub fn sample_heatmap(&self, max_samples: u32) -> Image {
+        let mut img = Image::new(self.width, self.height);
+        for j in 0..self.height {
+            for i in 0..self.width {
+                let t = (self.sample_count(i, j) as f64 / max_samples.max(1) as f64).clamp(0.0, 1.0);
+                let c = Color::new(
+                    (3.0 * t).min(1.0),
+                    (3.0 * t - 1.0).clamp(0.0, 1.0),
+                    (3.0 * t - 2.0).clamp(0.0, 1.0),
+                );
+                img.set(i, j, c);
+            }
+        }
+        return img;
+    }
+
+    /// Writes the accumulated state along with the render `seed` and the number of
+    /// progressive `passes` it took to get here
+    pub fn write_checkpoint<W: Write>(&self, writer: &mut W, seed: u64, passes: u32) -> io::Result<()> {
+        writeln!(writer, "CKPT {} {} {} {}", self.width, self.height, seed, passes)?;
+        for idx in 0..self.sum.len() {
+            let stats = &self.stats[idx];
+            let values = [
+                self.sum[idx].x(),
+                self.sum[idx].y(),
+                self.sum[idx].z(),
+                self.weight[idx],
+                stats.sum,
+                stats.sum_sq,
+            ];
+            for v in values {
+                writer.write_all(&v.to_le_bytes())?;
+            }
+            writer.write_all(&stats.count.to_le_bytes())?;
+        }
+        return Ok(());
+    }
+
+    /// Replaces the accumulated state with a checkpoint of a film of the same size,
+    /// returning the seed and pass count it was written with
+    pub fn read_checkpoint<R: BufRead>(&mut self, reader: &mut R) -> io::Result<(u64, u32)> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        if fields.len() != 5 || fields[0] != "CKPT" {
+            return Err(invalid("expected `CKPT <width> <height> <seed> <passes>` header"));
+        }
+        let parsed: Vec<u64> = fields[1..]
+            .iter()
+            .map(|f| f.parse::<u64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid("checkpoint header contains a non integer field"))?;
+        if parsed[0] != self.width as u64 || parsed[1] != self.height as u64 {
+            return Err(invalid("checkpoint resolution does not match the film"));
+        }
+        let passes = u32::try_from(parsed[3]).map_err(|_| invalid("checkpoint pass count out of range"))?;
+
+        let mut f = [0u8; 8];
+        let mut next = |reader: &mut R| -> io::Result<f64> {
+            reader.read_exact(&mut f)?;
+            return Ok(f64::from_le_bytes(f));
+        };
+        for idx in 0..self.sum.len() {
+            self.sum[idx] = Color::new(next(reader)?, next(reader)?, next(reader)?);
+            self.weight[idx] = next(reader)?;
+            self.stats[idx].sum = next(reader)?;
+            self.stats[idx].sum_sq = next(reader)?;
+            let mut count = [0u8; 4];
+            reader.read_exact(&mut count)?;
+            self.stats[idx].count = u32::from_le_bytes(count);
+        }
+        return Ok((parsed[2], passes));
+    }
+
+    pub fn pixel(&self, i: usize, j: usize) -> Color {
+        let idx = j * self.width + i;
+        let w = self.weight[idx];
+        // Negative filter lobes can leave tiny or negative weight sums and negative
+        // colors behind, neither of which is displayable
+        if w <= 1e-12 {
+            return Color::default();
+        }
+        let c = self.sum[idx] / w;
+        return Color::new(c.x().max(0.0), c.y().max(0.0), c.z().max(0.0));
+    }
+
+    pub fn to_image(&self) -> Image {
+        let mut img = Image::new(self.width, self.height);
+        for j in 0..self.height {
+            for i in 0..self.width {
+                img.set(i, j, self.pixel(i, j));
+            }
+        }
+        return img;
+    }
+}
+
+#[test]
+fn film_box_filter_averages_pixel() {
+    let mut film = Film::new(2, 1, Filter::default());
+    film.add_sample(0.2, 0.1, Color::new(1, 0, 0));
+    film.add_sample(-0.3, -0.4, Color::new(0, 1, 0));
+    film.add_sample(1.0, 0.0, Color::new(0, 0, 4));
+
+    assert_eq!(film.pixel(0, 0), Color::new(0.5, 0.5, 0.0));
+    assert_eq!(film.pixel(1, 0), Color::new(0, 0, 4));
+}
+
+#[test]
+fn film_negative_lobes_stay_displayable() {
+    let mut film = Film::new(3, 1, Filter::lanczos(3.0));
+    film.add_sample(0.0, 0.0, Color::new(10, 10, 10));
+    film.add_sample(1.5, 0.0, Color::new(0, 0, 0));
+
+    for i in 0..3 {
+        let c = film.pixel(i, 0);
+        assert!(c.x() >= 0.0 && c.y() >= 0.0 && c.z() >= 0.0);
+    }
+}
+
+#[test]
+fn film_tracks_pixel_convergence() {
+    let mut film = Film::new(2, 1, Filter::default());
+    assert_eq!(film.relative_error(0, 0), f64::INFINITY);
+
+    for k in 0..64 {
+        film.record_sample(0, 0, Color::new(0.5, 0.5, 0.5));
+        let v = if k % 2 == 0 { 0.0 } else { 1.0 };
+        film.record_sample(1, 0, Color::new(v, v, v));
+    }
+
+    assert_eq!(film.sample_count(0, 0), 64);
+    assert!(film.relative_error(0, 0) < 1e-6);
+    assert!(film.relative_error(1, 0) > 0.1);
+
+    let heat = film.sample_heatmap(64);
+    assert_eq!(heat.get(1, 0), Color::new(1, 1, 1));
+}
+
+#[test]
+fn film_checkpoint_roundtrip() {
+    let mut film = Film::new(3, 2, Filter::tent(1.0));
+    film.add_sample(0.3, 0.6, Color::new(0.1, 2.0, 0.7));
+    film.add_sample(2.2, 1.1, Color::new(1.0 / 3.0, 0.0, 5.0));
+    film.record_sample(0, 1, Color::new(0.1, 2.0, 0.7));
+
+    let mut bytes = Vec::new();
+    film.write_checkpoint(&mut bytes, 42, 3).unwrap();
+
+    let mut restored = Film::new(3, 2, Filter::tent(1.0));
+    assert_eq!(restored.read_checkpoint(&mut &bytes[..]).unwrap(), (42, 3));
+    assert_eq!(restored.to_image(), film.to_image());
+    assert_eq!(restored.sample_count(0, 1), 1);
+
+    let mut wrong_size = Film::new(2, 2, Filter::tent(1.0));
+    assert!(wrong_size.read_checkpoint(&mut &bytes[..]).is_err());
+}
@@ -1,11 +1,30 @@
-use std::rc::Rc;
+use std::{collections::HashMap, rc::Rc};
 
-use crate::{material::{Lambertian, Material}, util::{
-    color::Color, interval::Interval, ray::Ray, vec::{dot, Point3, Vec3}
+use crate::{light::Light, material::{Lambertian, Material}, util::{
+    aabb::Aabb, color::Color, interval::Interval, random_f64, ray::Ray, vec::{dot, Point3, Vec3}
 }};
 
 pub trait Hittable {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
+
+    /// Box enclosing the object over the whole shutter interval,
+    /// so moving objects stay inside it at any ray time
+    fn bounding_box(&self) -> Aabb;
+
+    /// Takes the object and material IDs its hits report from `ids`. Called by
+    /// `HittableList::add`, objects made of others pass it on to them in order.
+    fn assign_ids(&self, _ids: &mut SceneIds) {}
+}
+
+/// Hands out the IDs of the object and material ID passes in the order things are
+/// added to the scene, so the same scene gets the same IDs in every run. IDs start at
+/// one and stay within 24 bits so they survive being stored in a 32 bit float image.
+#[derive(Debug, Default)]
+pub struct SceneIds {
+    objects: u32,
+    materials: u32,
+    /// Materials seen so far by address, kept alive so no other material can reuse one
+    seen: HashMap<usize, (u32, Rc<dyn Material>)>,
 }
 
 #[derive(Debug, Clone)]
@@ -14,11 +33,25 @@ pub struct HitRecord {
     pub normal: Vec3,
     pub mat: Rc<dyn Material>,
     pub t: f64,
+    /// Surface coordinates of the hit in [0, 1], for texture lookups
+    pub u: f64,
+    pub v: f64,
+    /// Derivatives of the hit point along `u` and `v`, spanning the surface's tangent
+    /// plane. Zero where the surface has no parameterization.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub front_facing: bool,
+    /// Area light the hit surface belongs to, if it is sampled as one
+    pub light: Option<Rc<dyn Light>>,
+    /// Identifiers for the object and material ID passes, zero when unset
+    pub object_id: u32,
+    pub material_id: u32,
 }
 
 pub struct HittableList {
     objects: Vec<Rc<dyn Hittable>>,
+    bbox: Aabb,
+    ids: SceneIds,
 }
 
 impl Default for HitRecord {
@@ -28,8 +61,58 @@ impl Default for HitRecord {
             normal: Default::default(),
             mat: Rc::new(Lambertian::new(Color::random())),
             t: Default::default(),
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::default(),
+            dpdv: Vec3::default(),
             front_facing: Default::default(),
+            light: None,
+            object_id: 0,
+            material_id: 0,
+        }
+    }
+}
+
+/// Whether a hit on a surface with `mat` is kept, consulting its opacity. Fully opaque
+/// materials, the common case, don't use up a random number. Every call makes a fresh
+/// decision, so objects that hit the same surface more than once per ray, such as the
+/// boundaries of media, don't see a consistent cutout.
+pub fn passes_opacity(mat: &dyn Material, rec: &HitRecord) -> bool {
+    let alpha = mat.opacity(rec);
+    if alpha >= 1.0 {
+        return true;
+    }
+    return alpha > 0.0 && random_f64() < alpha;
+}
+
+/// Object and material ID of the scene wide fog, which isn't added to the scene
+pub const FOG_ID: u32 = 0xffffff;
+
+impl SceneIds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn next_object(&mut self) -> u32 {
+        self.objects = (self.objects + 1).min(FOG_ID - 1);
+        return self.objects;
+    }
+
+    /// ID for materials made on the fly, such as the collisions inside a medium
+    pub fn next_material(&mut self) -> u32 {
+        self.materials = (self.materials + 1).min(FOG_ID - 1);
+        return self.materials;
+    }
+
+    /// ID of `mat`, the same for every object sharing it
+    pub fn material(&mut self, mat: &Rc<dyn Material>) -> u32 {
+        let key = Rc::as_ptr(mat) as *const () as usize;
+        if let Some((id, _)) = self.seen.get(&key) {
+            return *id;
         }
+        let id = self.next_material();
+        self.seen.insert(key, (id, mat.clone()));
+        return id;
     }
 }
 
@@ -48,6 +131,8 @@ impl Default for HittableList {
     fn default() -> Self {
         Self {
             objects: Default::default(),
+            bbox: Default::default(),
+            ids: SceneIds::new(),
         }
     }
 }
@@ -68,6 +153,16 @@ impl Hittable for HittableList {
 
         return has_hit;
     }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.bbox;
+    }
+
+    fn assign_ids(&self, ids: &mut SceneIds) {
+        for o in &self.objects {
+            o.assign_ids(ids);
+        }
+    }
 }
 
 impl HittableList {
@@ -77,9 +172,50 @@ impl HittableList {
 
     pub fn clear(&mut self) {
         self.objects.clear();
+        self.bbox = Aabb::empty();
+        self.ids = SceneIds::new();
     }
 
+    /// Adds `object` and numbers it after the objects already in the list. A list
+    /// added to another one is numbered again as part of the outer list.
     pub fn add(&mut self, object: Rc<dyn Hittable>) {
+        self.bbox = Aabb::enclosing(&self.bbox, &object.bounding_box());
+        object.assign_ids(&mut self.ids);
         self.objects.push(object);
     }
+
+    pub fn objects(&self) -> &[Rc<dyn Hittable>] {
+        return &self.objects;
+    }
+}
+
+#[test]
+fn ids_follow_the_order_objects_are_added() {
+    use crate::sphere::Sphere;
+
+    let scene = || {
+        let shared: Rc<dyn Material> = Rc::new(Lambertian::default());
+        let mut inner = HittableList::new();
+        inner.add(Rc::new(Sphere::new(Point3::new(0, 0, -1), 0.5, shared.clone())));
+        inner.add(Rc::new(Sphere::new(Point3::new(2, 0, -1), 0.5, Rc::new(Lambertian::default()))));
+        let mut world = HittableList::new();
+        world.add(Rc::new(Sphere::new(Point3::new(-2, 0, -1), 0.5, shared)));
+        world.add(Rc::new(inner));
+        return world;
+    };
+    let ids = |world: &HittableList| -> Vec<(u32, u32)> {
+        [-2.0, 0.0, 2.0]
+            .iter()
+            .map(|&x| {
+                let mut rec = HitRecord::default();
+                let r = Ray::new(Point3::new(x, 0.0, 0.0), Vec3::new(0, 0, -1));
+                assert!(world.hit(&r, Interval::new(0.0, f64::INFINITY), &mut rec));
+                (rec.object_id, rec.material_id)
+            })
+            .collect()
+    };
+
+    // The nested list is numbered as part of the outer one, sharing the material's ID
+    assert_eq!(ids(&scene()), vec![(1, 1), (2, 1), (3, 2)]);
+    assert_eq!(ids(&scene()), ids(&scene()));
 }
@@ -1,5 +1,19 @@
 pub mod util;
+pub mod aov;
+pub mod bump;
+pub mod bvh;
+pub mod environment;
+pub mod film;
 pub mod hittable;
+pub mod sky;
 pub mod sphere;
 pub mod camera;
-pub mod material;
\ No newline at end of file
+pub mod denoise;
+pub mod light;
+pub mod light_sampler;
+pub mod material;
+pub mod medium;
+pub mod principled;
+pub mod progress;
+pub mod texture;
+pub mod tile;
\ No newline at end of file
@@ -0,0 +1,590 @@
+use std::{f64::consts::PI, fmt::Debug, rc::Rc};
+
+use crate::{
+    material::DiffuseLight,
+    sphere::Sphere,
+    util::{
+        random_f64,
+        aabb::Aabb,
+        color::{luminance, Color},
+        onb::Onb,
+        vec::{cross, dot, Point3, Vec3},
+    },
+};
+
+/// Light source sampled explicitly with shadow rays. Delta lights (point, spot and
+/// directional) can only be reached this way, area lights are also part of the
+/// scene geometry and can be hit by scattered rays.
+pub trait Light {
+    /// Samples light arriving at `p`, or `None` if `p` receives nothing from this light
+    fn sample_li(&self, p: Point3) -> Option<LightSample>;
+
+    /// Solid angle density with which `sample_li` picks unit direction `wi` from `p`,
+    /// always zero for delta lights
+    fn pdf_li(&self, _p: Point3, _wi: Vec3) -> f64 {
+        return 0.0;
+    }
+
+    fn is_delta(&self) -> bool {
+        return true;
+    }
+
+    /// Luminance of the total emitted power. Lights infinitely far away use the
+    /// scene bounds to estimate how much of their power reaches the scene.
+    fn power(&self, scene_bounds: &Aabb) -> f64;
+
+    /// Spatial and directional emission bounds, `None` for lights at infinity
+    fn bounds(&self) -> Option<LightBounds>;
+}
+
+impl Debug for dyn Light {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad("Light")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightSample {
+    /// Unit direction from the shaded point towards the light
+    pub wi: Vec3,
+    /// Incident radiance, or irradiance for delta lights
+    pub li: Color,
+    /// Distance to the light along `wi`, infinite for directional lights
+    pub dist: f64,
+    /// Solid angle density of `wi`, 1 for delta lights
+    pub pdf: f64,
+}
+
+/// Conservative bounds on where a light is and in which directions it emits,
+/// used to estimate its contribution to a point without sampling it.
+/// Emission is assumed to happen within `cos_theta_o` of the axis `w`, falling
+/// off to zero over a further `cos_theta_e`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightBounds {
+    pub bounds: Aabb,
+    pub phi: f64,
+    pub w: Vec3,
+    pub cos_theta_o: f64,
+    pub cos_theta_e: f64,
+    pub two_sided: bool,
+}
+
+/// Emissive sphere that can be sampled by solid angle from outside
+#[derive(Debug, Clone, Copy)]
+pub struct SphereLight {
+    center: Point3,
+    radius: f64,
+    radiance: Color,
+}
+
+#[derive(Default)]
+pub struct LightList {
+    lights: Vec<Rc<dyn Light>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PointLight {
+    position: Point3,
+    intensity: Color,
+}
+
+/// Angular profile of a spot light between its axis and the cone edge
+#[derive(Debug, Clone)]
+pub enum SpotFalloff {
+    /// Smoothstep from full intensity inside `falloff_start` to zero at the cone edge
+    Smooth { falloff_start: f64 },
+    /// Relative intensities sampled at equal angle steps from the axis to the cone edge,
+    /// like the vertical angles of an IES photometric file
+    Profile(Vec<f64>),
+}
+
+#[derive(Debug, Clone)]
+pub struct SpotLight {
+    position: Point3,
+    direction: Vec3,
+    intensity: Color,
+    cone_angle: f64,
+    falloff: SpotFalloff,
+}
+
+/// Infinitely distant light arriving from a single direction, like sunlight
+#[derive(Debug, Clone, Copy)]
+pub struct DirectionalLight {
+    direction: Vec3,
+    irradiance: Color,
+}
+
+impl LightList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, light: Rc<dyn Light>) {
+        self.lights.push(light);
+    }
+
+    pub fn clear(&mut self) {
+        self.lights.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.lights.is_empty();
+    }
+
+    pub fn lights(&self) -> &[Rc<dyn Light>] {
+        return &self.lights;
+    }
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> Self {
+        Self {
+            position: position,
+            intensity: intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, p: Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let dist_sq = to_light.length_squared();
+        if dist_sq == 0.0 {
+            return None;
+        }
+        let dist = dist_sq.sqrt();
+
+        Some(LightSample {
+            wi: to_light / dist,
+            li: self.intensity / dist_sq,
+            dist: dist,
+            pdf: 1.0,
+        })
+    }
+
+    fn power(&self, _scene_bounds: &Aabb) -> f64 {
+        return 4.0 * PI * luminance(&self.intensity);
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: Aabb::from_points(self.position, self.position),
+            phi: self.power(&Aabb::empty()),
+            w: Vec3::new(0, 0, 1),
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+            two_sided: false,
+        })
+    }
+}
+
+impl SpotLight {
+    /// Spot light shining along `direction` within a cone of half angle `cone_angle`,
+    /// with full intensity up to `falloff_start` (both in radians)
+    pub fn new(position: Point3, direction: Vec3, intensity: Color, cone_angle: f64, falloff_start: f64) -> Self {
+        // `clamp` keeps NaN, which would make every direction fall outside the cone test
+        let cone_angle = if cone_angle.is_nan() { 0.0 } else { cone_angle.clamp(0.0, PI) };
+        Self {
+            position: position,
+            direction: direction.to_normal(),
+            intensity: intensity,
+            cone_angle: cone_angle,
+            falloff: SpotFalloff::Smooth {
+                falloff_start: falloff_start.max(0.0).min(cone_angle),
+            },
+        }
+    }
+
+    pub fn with_profile(mut self, profile: Vec<f64>) -> Self {
+        self.falloff = SpotFalloff::Profile(profile);
+        return self;
+    }
+
+    /// Cosines of the angles where the falloff starts and where emission ends
+    fn cone_cosines(&self) -> (f64, f64) {
+        let start = match &self.falloff {
+            SpotFalloff::Smooth { falloff_start } => *falloff_start,
+            SpotFalloff::Profile(_) => 0.0,
+        };
+        return (start.cos(), self.cone_angle.cos());
+    }
+
+    /// Intensity scale for light leaving at `angle` radians from the spot axis
+    pub fn falloff(&self, angle: f64) -> f64 {
+        if angle > self.cone_angle {
+            return 0.0;
+        }
+        return match &self.falloff {
+            SpotFalloff::Smooth { falloff_start } => {
+                if angle <= *falloff_start {
+                    1.0
+                } else {
+                    let x = (self.cone_angle - angle) / (self.cone_angle - falloff_start);
+                    x * x * (3.0 - 2.0 * x)
+                }
+            }
+            SpotFalloff::Profile(values) => {
+                if values.is_empty() {
+                    return 1.0;
+                }
+                if values.len() == 1 || self.cone_angle == 0.0 {
+                    return values[0];
+                }
+                let x = angle / self.cone_angle * (values.len() - 1) as f64;
+                let i = (x.floor() as usize).min(values.len() - 2);
+                let f = x - i as f64;
+                (1.0 - f) * values[i] + f * values[i + 1]
+            }
+        };
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, p: Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let dist_sq = to_light.length_squared();
+        if dist_sq == 0.0 {
+            return None;
+        }
+        let dist = dist_sq.sqrt();
+        let wi = to_light / dist;
+
+        let angle = dot(-wi, self.direction).clamp(-1.0, 1.0).acos();
+        let scale = self.falloff(angle);
+        if scale <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            wi: wi,
+            li: scale * self.intensity / dist_sq,
+            dist: dist,
+            pdf: 1.0,
+        })
+    }
+
+    fn power(&self, _scene_bounds: &Aabb) -> f64 {
+        // Full intensity over the inner cone, roughly half of it across the falloff
+        let (cos_start, cos_end) = self.cone_cosines();
+        return 2.0 * PI * luminance(&self.intensity) * ((1.0 - cos_start) + 0.5 * (cos_start - cos_end));
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let (cos_start, cos_end) = self.cone_cosines();
+        Some(LightBounds {
+            bounds: Aabb::from_points(self.position, self.position),
+            phi: 4.0 * PI * luminance(&self.intensity),
+            w: self.direction,
+            cos_theta_o: cos_start,
+            cos_theta_e: (cos_end.acos() - cos_start.acos()).cos(),
+            two_sided: false,
+        })
+    }
+}
+
+impl DirectionalLight {
+    /// Light travelling along `direction`
+    pub fn new(direction: Vec3, irradiance: Color) -> Self {
+        Self {
+            direction: direction.to_normal(),
+            irradiance: irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, _p: Point3) -> Option<LightSample> {
+        Some(LightSample {
+            wi: -self.direction,
+            li: self.irradiance,
+            dist: f64::INFINITY,
+            pdf: 1.0,
+        })
+    }
+
+    fn power(&self, scene_bounds: &Aabb) -> f64 {
+        let radius = 0.5 * (Point3::new(scene_bounds.x.size(), scene_bounds.y.size(), scene_bounds.z.size())).length();
+        if !radius.is_finite() {
+            return luminance(&self.irradiance);
+        }
+        return PI * radius * radius * luminance(&self.irradiance);
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        return None;
+    }
+}
+
+impl SphereLight {
+    pub fn new(center: Point3, radius: f64, radiance: Color) -> Self {
+        Self {
+            center: center,
+            radius: radius.max(0.0),
+            radiance: radiance,
+        }
+    }
+
+    /// Builds the visible sphere for this light, so that scattered rays hitting it
+    /// are weighted against light sampling
+    pub fn geometry(self: &Rc<Self>) -> Sphere {
+        let light: Rc<dyn Light> = self.clone();
+        return Sphere::new(self.center, self.radius, Rc::new(DiffuseLight::new(self.radiance)))
+            .with_area_light(light);
+    }
+
+    fn cos_theta_max(&self, p: Point3) -> Option<f64> {
+        let dist_sq = (self.center - p).length_squared();
+        let r_sq = self.radius * self.radius;
+        if dist_sq <= r_sq {
+            // Only the outside of the sphere emits
+            return None;
+        }
+        return Some((1.0 - r_sq / dist_sq).max(0.0).sqrt());
+    }
+}
+
+impl Light for SphereLight {
+    fn sample_li(&self, p: Point3) -> Option<LightSample> {
+        let cos_theta_max = self.cos_theta_max(p)?;
+        let to_center = self.center - p;
+
+        let cos_theta = 1.0 - random_f64() * (1.0 - cos_theta_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_f64();
+        let wi = Onb::new(to_center)
+            .transform(Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+            .to_normal();
+
+        // Distance to the near side of the sphere along wi
+        let h = dot(wi, to_center);
+        let discr = (h * h - to_center.length_squared() + self.radius * self.radius).max(0.0);
+        let dist = h - discr.sqrt();
+
+        Some(LightSample {
+            wi: wi,
+            li: self.radiance,
+            dist: dist,
+            pdf: 1.0 / (2.0 * PI * (1.0 - cos_theta_max)),
+        })
+    }
+
+    fn pdf_li(&self, p: Point3, wi: Vec3) -> f64 {
+        let cos_theta_max = match self.cos_theta_max(p) {
+            Some(c) => c,
+            None => return 0.0,
+        };
+        if dot(wi.to_normal(), (self.center - p).to_normal()) < cos_theta_max {
+            return 0.0;
+        }
+        return 1.0 / (2.0 * PI * (1.0 - cos_theta_max));
+    }
+
+    fn is_delta(&self) -> bool {
+        return false;
+    }
+
+    fn power(&self, _scene_bounds: &Aabb) -> f64 {
+        let area = 4.0 * PI * self.radius * self.radius;
+        return PI * area * luminance(&self.radiance);
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let rvec = Vec3::from(self.radius);
+        Some(LightBounds {
+            bounds: Aabb::from_points(self.center - rvec, self.center + rvec),
+            phi: self.power(&Aabb::empty()),
+            w: Vec3::new(0, 0, 1),
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+            two_sided: false,
+        })
+    }
+}
+
+impl LightBounds {
+    pub fn union(a: &LightBounds, b: &LightBounds) -> Self {
+        if a.phi == 0.0 {
+            return *b;
+        }
+        if b.phi == 0.0 {
+            return *a;
+        }
+        let (w, cos_theta_o) = cone_union(a.w, a.cos_theta_o, b.w, b.cos_theta_o);
+
+        Self {
+            bounds: Aabb::enclosing(&a.bounds, &b.bounds),
+            phi: a.phi + b.phi,
+            w: w,
+            cos_theta_o: cos_theta_o,
+            cos_theta_e: a.cos_theta_e.min(b.cos_theta_e),
+            two_sided: a.two_sided || b.two_sided,
+        }
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        return self.bounds.centroid();
+    }
+
+    /// Conservative estimate of the light arriving at `p` from everything inside these
+    /// bounds, following the light BVH of pbrt-v4. `n` may be zero for points in media.
+    pub fn importance(&self, p: Point3, n: Vec3) -> f64 {
+        let pc = self.bounds.centroid();
+        let diagonal = Vec3::new(self.bounds.x.size(), self.bounds.y.size(), self.bounds.z.size());
+        let d2 = (p - pc).length_squared().max(0.5 * diagonal.length());
+
+        let to_p = p - pc;
+        let wi = if to_p.near_zero() { self.w } else { to_p.to_normal() };
+        let mut cos_theta_w = dot(self.w, wi);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w = safe_sqrt(1.0 - cos_theta_w * cos_theta_w);
+
+        // Angle subtended by the bounds as seen from p
+        let radius = 0.5 * diagonal.length();
+        let cos_theta_b = if to_p.length_squared() <= radius * radius {
+            -1.0
+        } else {
+            safe_sqrt(1.0 - radius * radius / to_p.length_squared())
+        };
+        let sin_theta_b = safe_sqrt(1.0 - cos_theta_b * cos_theta_b);
+
+        // Smallest possible angle between the emission cone and the direction to p
+        let sin_theta_o = safe_sqrt(1.0 - self.cos_theta_o * self.cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p < self.cos_theta_e {
+            return 0.0;
+        }
+
+        let mut importance = self.phi * cos_theta_p / d2;
+
+        if !n.near_zero() {
+            let cos_theta_i = dot(-wi, n.to_normal()).abs();
+            let sin_theta_i = safe_sqrt(1.0 - cos_theta_i * cos_theta_i);
+            importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+        }
+        return importance.max(0.0);
+    }
+}
+
+/// Weight for combining two sampling strategies by multiple importance sampling
+#[inline]
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let f2 = f_pdf * f_pdf;
+    let g2 = g_pdf * g_pdf;
+    if f2 + g2 == 0.0 {
+        return 0.0;
+    }
+    return f2 / (f2 + g2);
+}
+
+#[inline]
+fn safe_sqrt(x: f64) -> f64 {
+    return x.max(0.0).sqrt();
+}
+
+/// Cosine of max(0, a - b) given the sines and cosines of a and b
+#[inline]
+fn cos_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        return 1.0;
+    }
+    return cos_a * cos_b + sin_a * sin_b;
+}
+
+/// Sine of max(0, a - b) given the sines and cosines of a and b
+#[inline]
+fn sin_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        return 0.0;
+    }
+    return sin_a * cos_b - cos_a * sin_b;
+}
+
+/// Smallest cone containing the two cones given by axis and cosine of the half angle
+fn cone_union(wa: Vec3, cos_a: f64, wb: Vec3, cos_b: f64) -> (Vec3, f64) {
+    let theta_a = cos_a.clamp(-1.0, 1.0).acos();
+    let theta_b = cos_b.clamp(-1.0, 1.0).acos();
+    let theta_d = dot(wa, wb).clamp(-1.0, 1.0).acos();
+
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return (wa, cos_a);
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return (wb, cos_b);
+    }
+
+    let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+    if theta_o >= PI {
+        return (wa, -1.0);
+    }
+
+    let theta_r = theta_o - theta_a;
+    let wr = cross(wa, wb);
+    if wr.length_squared() == 0.0 {
+        return (wa, -1.0);
+    }
+    return (rotate(wa, wr.to_normal(), theta_r), theta_o.cos());
+}
+
+/// Rotates `v` around the unit `axis` by `angle` radians
+#[inline]
+fn rotate(v: Vec3, axis: Vec3, angle: f64) -> Vec3 {
+    let (s, c) = angle.sin_cos();
+    return v * c + cross(axis, v) * s + axis * dot(axis, v) * (1.0 - c);
+}
+
+#[test]
+fn sphere_light_sample_matches_pdf() {
+    let light = SphereLight::new(Point3::new(0, 0, -3), 1.0, Color::new(1, 1, 1));
+    let p = Point3::new(0.5, 0.2, 0.0);
+
+    for _ in 0..100 {
+        let s = light.sample_li(p).unwrap();
+        assert!((light.pdf_li(p, s.wi) - s.pdf).abs() < 1e-9);
+        let hit = p + s.dist * s.wi;
+        assert!(((hit - Point3::new(0, 0, -3)).length() - 1.0).abs() < 1e-9);
+    }
+    assert_eq!(light.pdf_li(p, Vec3::new(0, 0, 1)), 0.0);
+    assert!(light.sample_li(Point3::new(0, 0, -3.5)).is_none());
+}
+
+#[test]
+fn light_bounds_cone_union() {
+    let (w, cos) = cone_union(Vec3::new(1, 0, 0), 1.0, Vec3::new(0, 1, 0), 1.0);
+
+    assert!((cos - (PI / 4.0).cos()).abs() < 1e-12);
+    assert!((w - Vec3::new(1, 1, 0).to_normal()).length() < 1e-12);
+}
+
+#[test]
+fn point_light_inverse_square() {
+    let light = PointLight::new(Point3::new(0, 2, 0), Color::new(4, 4, 4));
+    let s = light.sample_li(Point3::new(0, 0, 0)).unwrap();
+
+    assert_eq!(s.wi, Vec3::new(0, 1, 0));
+    assert_eq!(s.li, Color::new(1, 1, 1));
+    assert_eq!(s.dist, 2.0);
+}
+
+#[test]
+fn spot_light_falloff() {
+    let spot = SpotLight::new(Point3::new(0, 1, 0), Vec3::new(0, -1, 0), Color::new(1, 1, 1), 0.5, 0.25);
+
+    assert_eq!(spot.falloff(0.1), 1.0);
+    assert_eq!(spot.falloff(0.6), 0.0);
+    assert!(spot.falloff(0.4) > 0.0 && spot.falloff(0.4) < 1.0);
+    assert!(spot.sample_li(Point3::new(10, 0, 0)).is_none());
+
+    let profiled = spot.with_profile(vec![1.0, 0.5, 0.0]);
+    assert_eq!(profiled.falloff(0.25), 0.5);
+    assert_eq!(profiled.falloff(0.375), 0.25);
+
+    // Out of range or NaN angles are clamped instead of panicking
+    let closed = SpotLight::new(Point3::new(0, 1, 0), Vec3::new(0, -1, 0), Color::new(1, 1, 1), -0.5, 0.25);
+    assert_eq!(closed.falloff(0.1), 0.0);
+    let nan = SpotLight::new(Point3::new(0, 1, 0), Vec3::new(0, -1, 0), Color::new(1, 1, 1), f64::NAN, 0.25);
+    assert_eq!(nan.falloff(0.1), 0.0);
+}
@@ -0,0 +1,389 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    light::{Light, LightBounds, LightList},
+    util::{aabb::Aabb, distribution::Distribution1D, vec::{Point3, Vec3}},
+};
+
+/// Picks one light for next event estimation at a shading point
+pub trait LightSampler {
+    /// Chooses a light for the point `p` with surface normal `n` (zero inside media)
+    /// using the uniform number `u`
+    fn sample(&self, p: Point3, n: Vec3, u: f64) -> Option<SampledLight>;
+
+    /// Probability that `sample` returns `light` for the given point
+    fn pmf(&self, p: Point3, n: Vec3, light: &Rc<dyn Light>) -> f64;
+}
+
+#[derive(Debug, Clone)]
+pub struct SampledLight {
+    pub light: Rc<dyn Light>,
+    pub pmf: f64,
+}
+
+/// Strategy used by the camera to pick lights
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LightSampling {
+    Uniform,
+    #[default]
+    Power,
+    Bvh,
+}
+
+pub struct UniformLightSampler {
+    lights: Vec<Rc<dyn Light>>,
+}
+
+/// Picks lights proportionally to their emitted power, independent of the shading point
+pub struct PowerLightSampler {
+    lights: Vec<Rc<dyn Light>>,
+    distribution: Option<Distribution1D>,
+    index: HashMap<usize, usize>,
+}
+
+/// Light bounding volume hierarchy that prefers lights which are bright, close
+/// and facing the shading point. Lights at infinity are sampled uniformly on the side.
+pub struct BvhLightSampler {
+    nodes: Vec<LightBvhNode>,
+    bounded_lights: Vec<Rc<dyn Light>>,
+    infinite_lights: Vec<Rc<dyn Light>>,
+    /// Path from the root to each bounded light's leaf, one bit per level with 1 for the second child
+    trails: HashMap<usize, u64>,
+}
+
+struct LightBvhNode {
+    bounds: LightBounds,
+    /// Index of the light for leaves, of the second child for interior nodes.
+    /// The first child always directly follows its parent.
+    index: usize,
+    is_leaf: bool,
+}
+
+#[inline]
+fn light_key(light: &Rc<dyn Light>) -> usize {
+    return Rc::as_ptr(light) as *const () as usize;
+}
+
+impl LightSampling {
+    pub fn build(&self, lights: &LightList, scene_bounds: &Aabb) -> Box<dyn LightSampler> {
+        return match self {
+            LightSampling::Uniform => Box::new(UniformLightSampler::new(lights)),
+            LightSampling::Power => Box::new(PowerLightSampler::new(lights, scene_bounds)),
+            LightSampling::Bvh => Box::new(BvhLightSampler::new(lights)),
+        };
+    }
+}
+
+impl UniformLightSampler {
+    pub fn new(lights: &LightList) -> Self {
+        Self {
+            lights: lights.lights().to_vec(),
+        }
+    }
+}
+
+impl LightSampler for UniformLightSampler {
+    fn sample(&self, _p: Point3, _n: Vec3, u: f64) -> Option<SampledLight> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let n = self.lights.len();
+        let index = ((u * n as f64) as usize).min(n - 1);
+
+        Some(SampledLight {
+            light: self.lights[index].clone(),
+            pmf: 1.0 / n as f64,
+        })
+    }
+
+    fn pmf(&self, _p: Point3, _n: Vec3, light: &Rc<dyn Light>) -> f64 {
+        let key = light_key(light);
+        if !self.lights.iter().any(|l| light_key(l) == key) {
+            return 0.0;
+        }
+        return 1.0 / self.lights.len() as f64;
+    }
+}
+
+impl PowerLightSampler {
+    pub fn new(lights: &LightList, scene_bounds: &Aabb) -> Self {
+        let lights = lights.lights().to_vec();
+        let powers: Vec<f64> = lights.iter().map(|l| l.power(scene_bounds)).collect();
+        let index = lights
+            .iter()
+            .enumerate()
+            .map(|(i, l)| (light_key(l), i))
+            .collect();
+
+        Self {
+            distribution: if lights.is_empty() {
+                None
+            } else {
+                Some(Distribution1D::new(&powers))
+            },
+            lights: lights,
+            index: index,
+        }
+    }
+}
+
+impl LightSampler for PowerLightSampler {
+    fn sample(&self, _p: Point3, _n: Vec3, u: f64) -> Option<SampledLight> {
+        let distribution = self.distribution.as_ref()?;
+        let (index, pmf) = distribution.sample_discrete(u);
+
+        Some(SampledLight {
+            light: self.lights[index].clone(),
+            pmf: pmf,
+        })
+    }
+
+    fn pmf(&self, _p: Point3, _n: Vec3, light: &Rc<dyn Light>) -> f64 {
+        return match (&self.distribution, self.index.get(&light_key(light))) {
+            (Some(distribution), Some(&index)) => distribution.discrete_pdf(index),
+            _ => 0.0,
+        };
+    }
+}
+
+impl BvhLightSampler {
+    pub fn new(lights: &LightList) -> Self {
+        let mut sampler = Self {
+            nodes: Vec::new(),
+            bounded_lights: Vec::new(),
+            infinite_lights: Vec::new(),
+            trails: HashMap::new(),
+        };
+
+        let mut bounded: Vec<(usize, LightBounds)> = Vec::new();
+        for light in lights.lights() {
+            match light.bounds() {
+                Some(b) if b.phi > 0.0 => {
+                    bounded.push((sampler.bounded_lights.len(), b));
+                    sampler.bounded_lights.push(light.clone());
+                }
+                Some(_) => {}
+                None => sampler.infinite_lights.push(light.clone()),
+            }
+        }
+
+        if !bounded.is_empty() {
+            sampler.build(&mut bounded, 0, 0);
+        }
+        return sampler;
+    }
+
+    fn build(&mut self, lights: &mut [(usize, LightBounds)], trail: u64, depth: u32) -> LightBounds {
+        if lights.len() == 1 {
+            let (index, bounds) = lights[0];
+            self.nodes.push(LightBvhNode {
+                bounds: bounds,
+                index: index,
+                is_leaf: true,
+            });
+            self.trails.insert(light_key(&self.bounded_lights[index]), trail);
+            return bounds;
+        }
+
+        // Split at the median centroid along the axis where the centroids spread the most
+        let mut centroid_bounds = Aabb::empty();
+        for (_, b) in lights.iter() {
+            let c = b.centroid();
+            centroid_bounds = Aabb::enclosing(&centroid_bounds, &Aabb::from_points(c, c));
+        }
+        let axis = centroid_bounds.longest_axis();
+        lights.sort_by(|a, b| a.1.centroid()[axis].total_cmp(&b.1.centroid()[axis]));
+        let mid = lights.len() / 2;
+
+        let node = self.nodes.len();
+        self.nodes.push(LightBvhNode {
+            bounds: lights[0].1,
+            index: 0,
+            is_leaf: false,
+        });
+
+        let (left, right) = lights.split_at_mut(mid);
+        let left_bounds = self.build(left, trail, depth + 1);
+        self.nodes[node].index = self.nodes.len();
+        let right_bounds = self.build(right, trail | (1 << depth), depth + 1);
+
+        let bounds = LightBounds::union(&left_bounds, &right_bounds);
+        self.nodes[node].bounds = bounds;
+        return bounds;
+    }
+
+    fn infinite_probability(&self) -> f64 {
+        let n_infinite = self.infinite_lights.len() as f64;
+        let n_bvh = if self.nodes.is_empty() { 0.0 } else { 1.0 };
+        if n_infinite + n_bvh == 0.0 {
+            return 0.0;
+        }
+        return n_infinite / (n_infinite + n_bvh);
+    }
+
+    /// Probabilities of descending into the first and second child of an interior node
+    fn child_probabilities(&self, node: usize, p: Point3, n: Vec3) -> Option<(f64, f64)> {
+        let c0 = self.nodes[node + 1].bounds.importance(p, n);
+        let c1 = self.nodes[self.nodes[node].index].bounds.importance(p, n);
+        if c0 == 0.0 && c1 == 0.0 {
+            return None;
+        }
+        return Some((c0 / (c0 + c1), c1 / (c0 + c1)));
+    }
+}
+
+impl LightSampler for BvhLightSampler {
+    fn sample(&self, p: Point3, n: Vec3, u: f64) -> Option<SampledLight> {
+        let p_infinite = self.infinite_probability();
+        let mut u = u;
+
+        if u < p_infinite {
+            let count = self.infinite_lights.len();
+            let index = ((u / p_infinite * count as f64) as usize).min(count - 1);
+            return Some(SampledLight {
+                light: self.infinite_lights[index].clone(),
+                pmf: p_infinite / count as f64,
+            });
+        }
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        u = ((u - p_infinite) / (1.0 - p_infinite)).min(1.0 - f64::EPSILON);
+        let mut node = 0;
+        let mut pmf = 1.0 - p_infinite;
+        loop {
+            if self.nodes[node].is_leaf {
+                if node == 0 && self.nodes[0].bounds.importance(p, n) == 0.0 {
+                    return None;
+                }
+                return Some(SampledLight {
+                    light: self.bounded_lights[self.nodes[node].index].clone(),
+                    pmf: pmf,
+                });
+            }
+
+            let (p0, p1) = self.child_probabilities(node, p, n)?;
+            if u < p0 {
+                node += 1;
+                u = (u / p0).min(1.0 - f64::EPSILON);
+                pmf *= p0;
+            } else {
+                node = self.nodes[node].index;
+                u = ((u - p0) / p1).min(1.0 - f64::EPSILON);
+                pmf *= p1;
+            }
+        }
+    }
+
+    fn pmf(&self, p: Point3, n: Vec3, light: &Rc<dyn Light>) -> f64 {
+        let p_infinite = self.infinite_probability();
+        let key = light_key(light);
+
+        let mut trail = match self.trails.get(&key) {
+            Some(&trail) => trail,
+            None => {
+                if self.infinite_lights.iter().any(|l| light_key(l) == key) {
+                    return p_infinite / self.infinite_lights.len() as f64;
+                }
+                return 0.0;
+            }
+        };
+
+        let mut node = 0;
+        let mut pmf = 1.0 - p_infinite;
+        loop {
+            if self.nodes[node].is_leaf {
+                if node == 0 && self.nodes[0].bounds.importance(p, n) == 0.0 {
+                    return 0.0;
+                }
+                return pmf;
+            }
+
+            let (p0, p1) = match self.child_probabilities(node, p, n) {
+                Some(probs) => probs,
+                None => return 0.0,
+            };
+            if trail & 1 == 0 {
+                node += 1;
+                pmf *= p0;
+            } else {
+                node = self.nodes[node].index;
+                pmf *= p1;
+            }
+            trail >>= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_lights() -> LightList {
+    use crate::{
+        light::{DirectionalLight, PointLight, SphereLight, SpotLight},
+        util::color::Color,
+    };
+
+    let mut lights = LightList::new();
+    for i in 0..12 {
+        let x = i as f64 * 1.5 - 8.0;
+        let intensity = 0.5 + (i % 4) as f64;
+        lights.add(Rc::new(SphereLight::new(Point3::new(x, 2, -3), 0.3, Color::from(intensity))));
+    }
+    lights.add(Rc::new(PointLight::new(Point3::new(0, 5, 0), Color::new(10, 10, 10))));
+    lights.add(Rc::new(SpotLight::new(
+        Point3::new(3, 3, 3),
+        Vec3::new(0, -1, 0),
+        Color::new(20, 20, 20),
+        0.6,
+        0.3,
+    )));
+    lights.add(Rc::new(DirectionalLight::new(Vec3::new(0, -1, 0), Color::new(1, 1, 1))));
+    return lights;
+}
+
+#[test]
+fn light_sampler_pmfs_sum_to_one() {
+    let lights = test_lights();
+    let scene_bounds = Aabb::from_points(Point3::new(-10, -1, -10), Point3::new(10, 6, 10));
+    let points = [
+        (Point3::new(0, 0, 0), Vec3::new(0, 1, 0)),
+        (Point3::new(-7, 1, -2), Vec3::new(1, 0, 0)),
+        (Point3::new(4, -0.5, 6), Vec3::default()),
+    ];
+
+    for strategy in [LightSampling::Uniform, LightSampling::Power, LightSampling::Bvh] {
+        let sampler = strategy.build(&lights, &scene_bounds);
+        for (p, n) in points {
+            let total: f64 = lights.lights().iter().map(|l| sampler.pmf(p, n, l)).sum();
+            assert!((total - 1.0).abs() < 1e-9, "{:?} sums to {}", strategy, total);
+        }
+    }
+}
+
+#[test]
+fn light_sampler_sample_matches_pmf() {
+    let lights = test_lights();
+    let scene_bounds = Aabb::from_points(Point3::new(-10, -1, -10), Point3::new(10, 6, 10));
+    let p = Point3::new(-2, 0, -1);
+    let n = Vec3::new(0, 1, 0);
+
+    for strategy in [LightSampling::Uniform, LightSampling::Power, LightSampling::Bvh] {
+        let sampler = strategy.build(&lights, &scene_bounds);
+        for i in 0..200 {
+            let sampled = sampler.sample(p, n, (i as f64 + 0.5) / 200.0).unwrap();
+            assert!((sampler.pmf(p, n, &sampled.light) - sampled.pmf).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn light_bvh_prefers_nearby_lights() {
+    let lights = test_lights();
+    let sampler = BvhLightSampler::new(&lights);
+    let first = &lights.lights()[0];
+    let last = &lights.lights()[11];
+    let p = Point3::new(-8, 1.5, -3);
+    let n = Vec3::new(0, 1, 0);
+
+    assert!(sampler.pmf(p, n, first) > sampler.pmf(p, n, last));
+}
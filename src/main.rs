@@ -1,13 +1,113 @@
+use std::env;
+use std::path::PathBuf;
+use std::process;
 use std::rc::Rc;
+use std::time::Duration;
 
+use raytracer::aov::{save_aovs, Aov};
+use raytracer::bvh::BvhNode;
 use raytracer::camera::Camera;
+use raytracer::denoise::Denoiser;
 use raytracer::hittable::HittableList;
 use raytracer::material::{Lambertian, Metal};
+use raytracer::progress::ProgressBar;
 use raytracer::sphere::Sphere;
+use raytracer::tile::CropWindow;
 use raytracer::util::color::Color;
+use raytracer::util::colorspace::ColorSpace;
+use raytracer::util::tonemap::{DisplayTransform, ToneMap};
 use raytracer::util::vec::Point3;
 
+struct Options {
+    snapshot: Option<PathBuf>,
+    checkpoint: Option<PathBuf>,
+    resume: bool,
+    crop: Option<CropWindow>,
+    aovs: Option<PathBuf>,
+    denoise: bool,
+    display: DisplayTransform,
+    output_space: ColorSpace,
+    spectral: bool,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: raytracer [--snapshot <file>] [--checkpoint <file>] [--resume] [--crop <x0,y0,x1,y1>] [--aovs <prefix>] [--denoise]\n       [--exposure <ev>] [--tonemap <clamp|reinhard|reinhard:<white>|hable|aces>]\n       [--output-space <srgb|acescg|p3>] [--spectral]");
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options {
+        snapshot: None,
+        checkpoint: None,
+        resume: false,
+        crop: None,
+        aovs: None,
+        denoise: false,
+        display: DisplayTransform::default(),
+        output_space: ColorSpace::default(),
+        spectral: false,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--snapshot" => opts.snapshot = Some(args.next().unwrap_or_else(|| usage()).into()),
+            "--checkpoint" => opts.checkpoint = Some(args.next().unwrap_or_else(|| usage()).into()),
+            "--resume" => opts.resume = true,
+            "--aovs" => opts.aovs = Some(args.next().unwrap_or_else(|| usage()).into()),
+            "--denoise" => opts.denoise = true,
+            "--spectral" => opts.spectral = true,
+            "--exposure" => {
+                opts.display.exposure = args
+                    .next()
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--output-space" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                opts.output_space = name.parse::<ColorSpace>().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    usage()
+                })
+            }
+            "--tonemap" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                opts.display.tone_map = name.parse::<ToneMap>().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    usage()
+                })
+            }
+            "--crop" => opts.crop = Some(parse_crop(&args.next().unwrap_or_else(|| usage()))),
+            _ => usage(),
+        }
+    }
+    if opts.resume && opts.checkpoint.is_none() {
+        eprintln!("--resume needs a --checkpoint file to resume from");
+        usage();
+    }
+    return opts;
+}
+
+/// Crop corners as fractions of the frame, e.g. `0.25,0.25,0.75,0.5`
+fn parse_crop(arg: &str) -> CropWindow {
+    let v: Vec<f64> = arg
+        .split(',')
+        .map(|f| f.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|_| usage());
+    if v.len() != 4 {
+        usage();
+    }
+    return CropWindow::Normalized {
+        x0: v[0],
+        y0: v[1],
+        x1: v[2],
+        y1: v[3],
+    };
+}
+
 fn main() {
+    let opts = parse_args();
+
     let mut world = HittableList::new();
     
     let ground_mat = Rc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
@@ -25,7 +125,41 @@ fn main() {
     cam.aspect_ratio = 16.0 / 9.0;
     cam.image_width = 768;
     cam.samples_per_pixel = 500;
+    cam.min_samples_per_pixel = 32;
+    cam.noise_threshold = 0.01;
+    cam.pass_samples = 32;
+    cam.snapshot_path = opts.snapshot.clone();
+    cam.snapshot_interval = Duration::from_secs(10);
+    cam.observer = Some(Box::new(ProgressBar::new()));
     cam.max_depth = 50;
+    cam.checkpoint_path = opts.checkpoint.clone();
+    cam.checkpoint_interval = Duration::from_secs(60);
+    cam.crop = opts.crop;
+    cam.display = opts.display;
+    cam.output_space = opts.output_space;
+    cam.spectral = opts.spectral;
+    if opts.denoise {
+        cam.denoiser = Some(Denoiser::new());
+    }
+    if opts.aovs.is_some() {
+        cam.aovs = Aov::ALL.to_vec();
+    }
+
+    let world = BvhNode::from_list(&world);
+    match &opts.checkpoint {
+        Some(path) if opts.resume => {
+            if let Err(e) = cam.resume(&world, path) {
+                eprintln!("cannot resume from {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+        _ => cam.render(&world),
+    }
 
-    cam.render(&world);
+    if let Some(prefix) = &opts.aovs {
+        if let Err(e) = save_aovs(cam.aov_images(), prefix) {
+            eprintln!("failed to write AOVs to {}: {}", prefix.display(), e);
+            process::exit(1);
+        }
+    }
 }
@@ -1,8 +1,19 @@
-use std::fmt::Debug;
+use std::{f64::consts::PI, fmt::Debug, rc::Rc};
 
 use crate::{
     hittable::HitRecord,
-    util::{color::Color, ray::Ray, vec::Vec3},
+    texture::{SolidColor, Texture},
+    util::{
+        color::Color,
+        ior::{Ior, D_LINE},
+        microfacet::fresnel_dielectric,
+        phase::{henyey_greenstein, sample_henyey_greenstein},
+        random_f64,
+        ray::Ray,
+        spectrum::{reflectance_spectrum, reflectance_to_rgb, SigmoidSpectrum},
+        thinfilm::{airy_reflectance, Substrate},
+        vec::{dot, Vec3},
+    },
 };
 
 pub trait Material {
@@ -15,6 +26,58 @@ pub trait Material {
     ) -> bool {
         return false;
     }
+
+    /// `scatter`, also returning the density with which the direction was picked, or
+    /// zero when it came from a discrete lobe that light sampling can't reach. Materials
+    /// that choose between lobes at random override it to say which kind they chose.
+    fn scatter_with_pdf(
+        self: &Self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> Option<f64> {
+        if !self.scatter(r_in, rec, attenuation, scattered) {
+            return None;
+        }
+        return Some(self.pdf(r_in, rec, scattered.direction().to_normal()));
+    }
+
+    fn emitted(self: &Self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        return Color::default();
+    }
+
+    /// Fraction of light arriving from unit direction `wi` that is scattered back along
+    /// `r_in`, including the cosine term. Used for light sampling, so materials that only
+    /// scatter into discrete directions keep the default of zero.
+    fn eval(self: &Self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> Color {
+        return Color::default();
+    }
+
+    /// Solid angle density with which `scatter` picks unit direction `wi`,
+    /// zero for materials without an `eval`
+    fn pdf(self: &Self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> f64 {
+        return 0.0;
+    }
+
+    /// Overall reflectance at `rec`, written to the albedo pass
+    fn albedo(self: &Self, _rec: &HitRecord) -> Color {
+        return Color::default();
+    }
+
+    /// Chance that a ray hitting the surface at `rec` stops there rather than passing
+    /// through a cut out part. Called by `Hittable::hit` implementations, which skip the
+    /// hit and carry on along the ray when it doesn't.
+    fn opacity(self: &Self, _rec: &HitRecord) -> f64 {
+        return 1.0;
+    }
+
+    /// Whether `scatter` sends different wavelengths in different directions. The
+    /// integrator then restricts the path to a single wavelength, set on the ray,
+    /// before scattering.
+    fn is_dispersive(self: &Self) -> bool {
+        return false;
+    }
 }
 
 impl Debug for dyn Material {
@@ -28,9 +91,87 @@ pub struct Lambertian {
     albedo: Color,
 }
 
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone)]
 pub struct Metal {
     albedo: Color,
+    /// Thin film on top, with the spectrum of `albedo` it reflects off underneath
+    coating: Option<(ThinFilm, SigmoidSpectrum)>,
+}
+
+/// Emits light from the front side of a surface without scattering any
+#[derive(Default, Debug, Clone, Copy)]
+pub struct DiffuseLight {
+    emit: Color,
+}
+
+/// Smooth glass-like boundary that reflects or refracts, with a refractive index that
+/// may depend on wavelength
+#[derive(Default, Debug, Clone)]
+pub struct Dielectric {
+    ior: Ior,
+    tint: Color,
+    coating: Option<ThinFilm>,
+}
+
+/// Transparent coating a few hundred nanometres thick on top of a surface. Light
+/// reflected off its top and bottom interferes, giving the colors of soap bubbles,
+/// oil slicks and anodised metal.
+#[derive(Debug, Clone)]
+pub struct ThinFilm {
+    ior: f64,
+    thickness: Rc<dyn Texture>,
+    max_thickness: f64,
+}
+
+/// Blend of two materials, picking `b` with a probability given by the first channel
+/// of `weight` and `a` otherwise
+#[derive(Debug, Clone)]
+pub struct Mix {
+    a: Rc<dyn Material>,
+    b: Rc<dyn Material>,
+    weight: Rc<dyn Texture>,
+}
+
+/// Smooth clear coat over any base material. Light either reflects off the coating or
+/// passes through it to the base and back out, losing what the coating reflects on
+/// each crossing. Bending at the interface and reflections inside the coating are
+/// left out.
+#[derive(Debug, Clone)]
+pub struct Layered {
+    base: Rc<dyn Material>,
+    ior: f64,
+}
+
+/// Material with holes in it, such as a leaf cut out of a quad. The first channel of
+/// `opacity` is the chance a ray stops at the surface, or with a threshold set, the
+/// surface is solid where it reaches the threshold and missing elsewhere.
+#[derive(Debug, Clone)]
+pub struct Cutout {
+    inner: Rc<dyn Material>,
+    opacity: Rc<dyn Texture>,
+    threshold: Option<f64>,
+}
+
+/// Phase function material for participating media, scattering uniformly in all directions
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Isotropic {
+    albedo: Color,
+}
+
+/// Anisotropic phase function material for participating media
+#[derive(Default, Debug, Clone, Copy)]
+pub struct HenyeyGreenstein {
+    albedo: Color,
+    g: f64,
+}
+
+/// Phase function material produced at collisions inside a heterogeneous medium,
+/// carrying the medium's local albedo and emission
+#[derive(Default, Debug, Clone, Copy)]
+pub struct VolumeCollision {
+    albedo: Color,
+    emission: Color,
+    g: f64,
 }
 
 impl Lambertian {
@@ -40,15 +181,167 @@ impl Lambertian {
 }
 
 impl Metal {
+    pub fn new(albedo: Color) -> Self {
+        Self {
+            albedo: albedo,
+            coating: None,
+        }
+    }
+
+    pub fn coated(albedo: Color, film: ThinFilm) -> Self {
+        Self {
+            albedo: albedo,
+            coating: Some((film, reflectance_spectrum(albedo))),
+        }
+    }
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        Self { emit: emit }
+    }
+}
+
+impl Dielectric {
+    pub fn new(ior: Ior) -> Self {
+        Self::tinted(ior, Color::new(1, 1, 1))
+    }
+
+    /// Colors every reflection and refraction by `tint`
+    pub fn tinted(ior: Ior, tint: Color) -> Self {
+        Self {
+            ior: ior,
+            tint: tint,
+            coating: None,
+        }
+    }
+
+    pub fn with_coating(mut self, film: ThinFilm) -> Self {
+        self.coating = Some(film);
+        return self;
+    }
+
+    fn reflectance(cosine: f64, eta_ratio: f64) -> f64 {
+        // Schlick's approximation
+        let r0 = ((1.0 - eta_ratio) / (1.0 + eta_ratio)).powi(2);
+        return r0 + (1.0 - r0) * (1.0 - cosine).powi(5);
+    }
+}
+
+impl ThinFilm {
+    /// Film of index `ior` that is `thickness` nm thick everywhere
+    pub fn new(ior: f64, thickness: f64) -> Self {
+        Self::textured(ior, Rc::new(SolidColor::new(Color::new(1, 1, 1))), thickness)
+    }
+
+    /// Film whose thickness in nm is the first channel of `thickness` scaled by
+    /// `max_thickness`
+    pub fn textured(ior: f64, thickness: Rc<dyn Texture>, max_thickness: f64) -> Self {
+        Self {
+            ior: ior,
+            thickness: thickness,
+            max_thickness: max_thickness,
+        }
+    }
+
+    pub fn thickness(&self, rec: &HitRecord) -> f64 {
+        return (self.thickness.value(rec.u, rec.v, rec.p).x() * self.max_thickness).max(0.0);
+    }
+
+    /// Reflectance for light arriving from index `n_incident` at an angle with cosine
+    /// `cos_theta`. Rays restricted to a wavelength get the value at it, the rest the
+    /// linear sRGB color of the whole reflectance spectrum.
+    fn reflectance(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        cos_theta: f64,
+        n_incident: f64,
+        substrate: impl Fn(f64) -> Substrate,
+    ) -> Color {
+        let d = self.thickness(rec);
+        let r = |lambda: f64| airy_reflectance(cos_theta, n_incident, self.ior, d, substrate(lambda), lambda);
+        return match r_in.wavelength() {
+            Some(lambda) => Color::from(r(lambda)),
+            None => {
+                // Interference colors can lie outside the gamut
+                let c = reflectance_to_rgb(r);
+                Color::new(c.x().clamp(0.0, 1.0), c.y().clamp(0.0, 1.0), c.z().clamp(0.0, 1.0))
+            }
+        };
+    }
+}
+
+impl Mix {
+    pub fn new(a: Rc<dyn Material>, b: Rc<dyn Material>, weight: f64) -> Self {
+        return Self::textured(a, b, Rc::new(SolidColor::new(Color::from(weight))));
+    }
+
+    pub fn textured(a: Rc<dyn Material>, b: Rc<dyn Material>, weight: Rc<dyn Texture>) -> Self {
+        Self { a: a, b: b, weight: weight }
+    }
+
+    fn weight(&self, rec: &HitRecord) -> f64 {
+        return self.weight.value(rec.u, rec.v, rec.p).x().clamp(0.0, 1.0);
+    }
+}
+
+impl Layered {
+    pub fn new(base: Rc<dyn Material>, ior: f64) -> Self {
+        Self { base: base, ior: ior }
+    }
+
+    fn fresnel(&self, rec: &HitRecord, dir: Vec3) -> f64 {
+        return fresnel_dielectric(dot(rec.normal, dir.to_normal()).abs(), self.ior);
+    }
+}
+
+impl Cutout {
+    pub fn new(inner: Rc<dyn Material>, opacity: Rc<dyn Texture>) -> Self {
+        Self {
+            inner: inner,
+            opacity: opacity,
+            threshold: None,
+        }
+    }
+
+    /// Cuts deterministically instead, which avoids noise along the edges of masks that
+    /// are mostly zero or one
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = Some(threshold);
+        return self;
+    }
+}
+
+impl Isotropic {
     pub fn new(albedo: Color) -> Self {
         Self { albedo: albedo }
     }
 }
 
+impl HenyeyGreenstein {
+    pub fn new(albedo: Color, g: f64) -> Self {
+        Self {
+            albedo: albedo,
+            g: g.clamp(-0.999, 0.999),
+        }
+    }
+}
+
+impl VolumeCollision {
+    pub fn new(albedo: Color, emission: Color, g: f64) -> Self {
+        Self {
+            albedo: albedo,
+            emission: emission,
+            g: g.clamp(-0.999, 0.999),
+        }
+    }
+}
+
 impl Material for Lambertian {
     fn scatter(
         &self,
-        _r_in: &Ray,
+        r_in: &Ray,
         rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
@@ -58,10 +351,22 @@ impl Material for Lambertian {
         if scatter_dir.near_zero() {
             scatter_dir = rec.normal;
         }
-        *scattered = Ray::new(rec.p, scatter_dir);
+        *scattered = Ray::new_timed(rec.p, scatter_dir, r_in.time());
         *attenuation = self.albedo;
         return true;
     }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        return self.albedo * (dot(rec.normal, wi).max(0.0) / PI);
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        return dot(rec.normal, wi).max(0.0) / PI;
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        return self.albedo;
+    }
 }
 
 impl Material for Metal {
@@ -73,8 +378,340 @@ impl Material for Metal {
         scattered: &mut Ray,
     ) -> bool {
         let reflected = Vec3::reflect(r_in.direction(), rec.normal);
-        *scattered = Ray::new(rec.p, reflected);
+        *scattered = Ray::new_timed(rec.p, reflected, r_in.time());
+        *attenuation = match &self.coating {
+            Some((film, substrate)) => {
+                let cos_theta = dot(-r_in.direction().to_normal(), rec.normal);
+                film.reflectance(r_in, rec, cos_theta, 1.0, |lambda| Substrate::Mirror(substrate.eval(lambda)))
+            }
+            None => self.albedo,
+        };
+        return true;
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        return self.albedo;
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let ior = self.ior.at(r_in.wavelength().unwrap_or(D_LINE));
+        let (n_incident, n_transmitted) = if rec.front_facing { (1.0, ior) } else { (ior, 1.0) };
+        let eta_ratio = n_incident / n_transmitted;
+
+        let unit_dir = r_in.direction().to_normal();
+        let cos_theta = dot(-unit_dir, rec.normal).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+        let cannot_refract = eta_ratio * sin_theta > 1.0;
+        let reflect = match &self.coating {
+            _ if cannot_refract => {
+                *attenuation = self.tint;
+                true
+            }
+            Some(film) => {
+                // The film reflects each wavelength differently, so pick a branch by the
+                // mean reflectance and weight it by the actual one
+                let r = film.reflectance(r_in, rec, cos_theta, n_incident, |_| Substrate::Dielectric(n_transmitted));
+                let p = ((r.x() + r.y() + r.z()) / 3.0).clamp(1e-4, 1.0 - 1e-4);
+                let reflect = p > random_f64();
+                *attenuation = self.tint * if reflect { r / p } else { (Color::new(1, 1, 1) - r) / (1.0 - p) };
+                reflect
+            }
+            None => {
+                *attenuation = self.tint;
+                Dielectric::reflectance(cos_theta, eta_ratio) > random_f64()
+            }
+        };
+        let dir = if reflect {
+            Vec3::reflect(unit_dir, rec.normal)
+        } else {
+            Vec3::refract(unit_dir, rec.normal, eta_ratio)
+        };
+        *scattered = Ray::new_timed(rec.p, dir, r_in.time()).with_wavelength(r_in.wavelength());
+        return true;
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        return self.tint;
+    }
+
+    fn is_dispersive(&self) -> bool {
+        return self.ior.is_dispersive();
+    }
+}
+
+impl Material for Mix {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        return self.scatter_with_pdf(r_in, rec, attenuation, scattered).is_some();
+    }
+
+    fn scatter_with_pdf(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> Option<f64> {
+        let w = self.weight(rec);
+        let chosen = if random_f64() < w { &self.b } else { &self.a };
+        let pdf = chosen.scatter_with_pdf(r_in, rec, attenuation, scattered)?;
+        if pdf == 0.0 {
+            // Discrete lobes can't be picked by the other material, so the choice
+            // probability cancels against the blend weight
+            return Some(0.0);
+        }
+        // The direction could have come from either material, weight it by both
+        let wi = scattered.direction().to_normal();
+        let pdf = self.pdf(r_in, rec, wi);
+        *attenuation = self.eval(r_in, rec, wi) / pdf;
+        return Some(pdf);
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let w = self.weight(rec);
+        return (1.0 - w) * self.a.eval(r_in, rec, wi) + w * self.b.eval(r_in, rec, wi);
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        let w = self.weight(rec);
+        return (1.0 - w) * self.a.pdf(r_in, rec, wi) + w * self.b.pdf(r_in, rec, wi);
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        let w = self.weight(rec);
+        return (1.0 - w) * self.a.emitted(r_in, rec) + w * self.b.emitted(r_in, rec);
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        let w = self.weight(rec);
+        return (1.0 - w) * self.a.albedo(rec) + w * self.b.albedo(rec);
+    }
+
+    fn opacity(&self, rec: &HitRecord) -> f64 {
+        let w = self.weight(rec);
+        return (1.0 - w) * self.a.opacity(rec) + w * self.b.opacity(rec);
+    }
+
+    fn is_dispersive(&self) -> bool {
+        return self.a.is_dispersive() || self.b.is_dispersive();
+    }
+}
+
+impl Material for Layered {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        return self.scatter_with_pdf(r_in, rec, attenuation, scattered).is_some();
+    }
+
+    fn scatter_with_pdf(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> Option<f64> {
+        // Reflecting off the coat with its Fresnel probability leaves a weight of one
+        let fresnel_out = self.fresnel(rec, r_in.direction());
+        if random_f64() < fresnel_out {
+            let reflected = Vec3::reflect(r_in.direction(), rec.normal);
+            *scattered = Ray::new_timed(rec.p, reflected, r_in.time()).with_wavelength(r_in.wavelength());
+            *attenuation = Color::new(1, 1, 1);
+            return Some(0.0);
+        }
+        let pdf = self.base.scatter_with_pdf(r_in, rec, attenuation, scattered)?;
+        *attenuation = (1.0 - self.fresnel(rec, scattered.direction())) * *attenuation;
+        return Some((1.0 - fresnel_out) * pdf);
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let transmitted = (1.0 - self.fresnel(rec, r_in.direction())) * (1.0 - self.fresnel(rec, wi));
+        return transmitted * self.base.eval(r_in, rec, wi);
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        return (1.0 - self.fresnel(rec, r_in.direction())) * self.base.pdf(r_in, rec, wi);
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        return (1.0 - self.fresnel(rec, r_in.direction())) * self.base.emitted(r_in, rec);
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        return self.base.albedo(rec);
+    }
+
+    fn opacity(&self, rec: &HitRecord) -> f64 {
+        return self.base.opacity(rec);
+    }
+
+    fn is_dispersive(&self) -> bool {
+        return self.base.is_dispersive();
+    }
+}
+
+impl Material for Cutout {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        return self.inner.scatter(r_in, rec, attenuation, scattered);
+    }
+
+    fn scatter_with_pdf(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> Option<f64> {
+        return self.inner.scatter_with_pdf(r_in, rec, attenuation, scattered);
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        return self.inner.emitted(r_in, rec);
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        return self.inner.eval(r_in, rec, wi);
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        return self.inner.pdf(r_in, rec, wi);
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        return self.inner.albedo(rec);
+    }
+
+    fn opacity(&self, rec: &HitRecord) -> f64 {
+        let alpha = self.opacity.value(rec.u, rec.v, rec.p).x().clamp(0.0, 1.0);
+        return match self.threshold {
+            Some(threshold) if alpha >= threshold => 1.0,
+            Some(_) => 0.0,
+            None => alpha,
+        };
+    }
+
+    fn is_dispersive(&self) -> bool {
+        return self.inner.is_dispersive();
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        *scattered = Ray::new_timed(rec.p, Vec3::random_normal(), r_in.time());
+        *attenuation = self.albedo;
+        return true;
+    }
+
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> Color {
+        return self.albedo / (4.0 * PI);
+    }
+
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> f64 {
+        return 1.0 / (4.0 * PI);
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        return self.albedo;
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let dir = sample_henyey_greenstein(r_in.direction(), self.g);
+        *scattered = Ray::new_timed(rec.p, dir, r_in.time());
         *attenuation = self.albedo;
         return true;
     }
+
+    fn eval(&self, r_in: &Ray, _rec: &HitRecord, wi: Vec3) -> Color {
+        let cos_theta = dot(r_in.direction().to_normal(), wi);
+        return self.albedo * henyey_greenstein(cos_theta, self.g);
+    }
+
+    fn pdf(&self, r_in: &Ray, _rec: &HitRecord, wi: Vec3) -> f64 {
+        return henyey_greenstein(dot(r_in.direction().to_normal(), wi), self.g);
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        return self.albedo;
+    }
+}
+
+impl Material for VolumeCollision {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let dir = sample_henyey_greenstein(r_in.direction(), self.g);
+        *scattered = Ray::new_timed(rec.p, dir, r_in.time());
+        *attenuation = self.albedo;
+        return true;
+    }
+
+    fn eval(&self, r_in: &Ray, _rec: &HitRecord, wi: Vec3) -> Color {
+        let cos_theta = dot(r_in.direction().to_normal(), wi);
+        return self.albedo * henyey_greenstein(cos_theta, self.g);
+    }
+
+    fn pdf(&self, r_in: &Ray, _rec: &HitRecord, wi: Vec3) -> f64 {
+        return henyey_greenstein(dot(r_in.direction().to_normal(), wi), self.g);
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        return self.albedo;
+    }
+
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        // Collisions are sampled proportionally to the extinction, of which only
+        // the absorbed fraction emits
+        return (Color::new(1, 1, 1) - self.albedo) * self.emission;
+    }
+}
+
+impl Material for DiffuseLight {
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Color {
+        if !rec.front_facing {
+            return Color::default();
+        }
+        return self.emit;
+    }
 }
@@ -0,0 +1,483 @@
+use std::{cell::Cell, io, path::Path, rc::Rc};
+
+use crate::{
+    hittable::{HitRecord, Hittable, SceneIds, FOG_ID},
+    material::{Dielectric, Isotropic, Material, VolumeCollision},
+    util::{
+        random_f64,
+        ior::Ior,
+        spectrum::{reflectance_spectrum, SigmoidSpectrum},
+        aabb::Aabb,
+        color::Color,
+        interval::Interval,
+        ray::Ray,
+        vec::{Point3, Vec3},
+        voxel::VoxelGrid,
+    },
+};
+
+/// Homogeneous participating medium filling the inside of a closed boundary object.
+/// Only the boundary's shape is used, but its material's opacity still decides each
+/// crossing on its own, so a cutout on the boundary can find an entry without an exit.
+/// Boundaries should be given an opaque material.
+pub struct ConstantMedium {
+    boundary: Rc<dyn Hittable>,
+    neg_inv_density: f64,
+    phase_function: Rc<dyn Material>,
+    object_id: Cell<u32>,
+    material_id: Cell<u32>,
+}
+
+/// Homogeneous medium filling the whole scene, e.g. for atmospheric haze
+#[derive(Debug, Clone)]
+pub struct Fog {
+    density: f64,
+    phase_function: Rc<dyn Material>,
+}
+
+/// Samples the distance a ray travels through a medium of the given density
+/// before it scatters, as a ray parameter
+#[inline]
+pub fn sample_free_flight(r: &Ray, density: f64) -> f64 {
+    return -(1.0 - random_f64()).ln() / (density * r.direction().length());
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Rc<dyn Hittable>, density: f64, albedo: Color) -> Self {
+        return Self::with_phase(boundary, density, Rc::new(Isotropic::new(albedo)));
+    }
+
+    pub fn with_phase(boundary: Rc<dyn Hittable>, density: f64, phase_function: Rc<dyn Material>) -> Self {
+        Self {
+            boundary: boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function: phase_function,
+            object_id: Cell::new(0),
+            material_id: Cell::new(0),
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let mut rec1 = HitRecord::default();
+        let mut rec2 = HitRecord::default();
+
+        if !self.boundary.hit(r, Interval::universe(), &mut rec1) {
+            return false;
+        }
+        if !self
+            .boundary
+            .hit(r, Interval::new(rec1.t + 0.0001, f64::INFINITY), &mut rec2)
+        {
+            return false;
+        }
+
+        rec1.t = rec1.t.max(ray_t.min);
+        rec2.t = rec2.t.min(ray_t.max);
+        if rec1.t >= rec2.t {
+            return false;
+        }
+        rec1.t = rec1.t.max(0.0);
+
+        let ray_length = r.direction().length();
+        let distance_inside = (rec2.t - rec1.t) * ray_length;
+        let hit_distance = self.neg_inv_density * (1.0 - random_f64()).ln();
+
+        if hit_distance > distance_inside {
+            return false;
+        }
+
+        rec.t = rec1.t + hit_distance / ray_length;
+        rec.p = r.at(rec.t);
+        // Points inside a medium have no surface normal
+        rec.normal = Vec3::default();
+        rec.front_facing = true;
+        rec.mat = self.phase_function.clone();
+        rec.light = None;
+        rec.object_id = self.object_id.get();
+        rec.material_id = self.material_id.get();
+
+        return true;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.boundary.bounding_box();
+    }
+
+    fn assign_ids(&self, ids: &mut SceneIds) {
+        self.object_id.set(ids.next_object());
+        self.material_id.set(ids.material(&self.phase_function));
+    }
+}
+
+impl Fog {
+    pub fn new(density: f64, albedo: Color) -> Self {
+        return Self::with_phase(density, Rc::new(Isotropic::new(albedo)));
+    }
+
+    pub fn with_phase(density: f64, phase_function: Rc<dyn Material>) -> Self {
+        Self {
+            density: density,
+            phase_function: phase_function,
+        }
+    }
+
+    /// Fraction of light surviving a straight path of length `dist` through the fog
+    pub fn transmittance(&self, dist: f64) -> f64 {
+        if self.density <= 0.0 {
+            return 1.0;
+        }
+        return (-self.density * dist).exp();
+    }
+
+    /// Returns a scattering event if the ray scatters in the fog before reaching `t_max`
+    pub fn scatter_before(&self, r: &Ray, t_max: f64) -> Option<HitRecord> {
+        if self.density <= 0.0 {
+            return None;
+        }
+
+        let t = sample_free_flight(r, self.density);
+        if t >= t_max {
+            return None;
+        }
+
+        return Some(HitRecord {
+            t: t,
+            p: r.at(t),
+            normal: Vec3::default(),
+            front_facing: true,
+            mat: self.phase_function.clone(),
+            object_id: FOG_ID,
+            material_id: FOG_ID,
+            ..Default::default()
+        });
+    }
+}
+
+/// Translucent object such as skin, wax or marble, rendered with a volumetric random
+/// walk. The closed `boundary` is a smooth dielectric surface, and the inside a dense
+/// medium whose mean free path differs per color channel, so that red light travels
+/// further under the surface than blue. As with `ConstantMedium`, a cutout on the
+/// boundary's own material would be decided separately at each crossing, so the
+/// boundary should be opaque.
+pub struct SubsurfaceMedium {
+    boundary: Rc<dyn Hittable>,
+    albedo: Color,
+    mean_free_path: Color,
+    /// Mean free path over the visible range for spectral rendering, scaled to [0, 1]
+    spectrum: SigmoidSpectrum,
+    spectrum_scale: f64,
+    surface: Rc<SubsurfaceBoundary>,
+    collision: Rc<dyn Material>,
+    object_id: Cell<u32>,
+    surface_id: Cell<u32>,
+    collision_id: Cell<u32>,
+}
+
+/// Surface of a `SubsurfaceMedium`. It is dispersive because each wavelength takes
+/// its own walk under the surface, so the path is narrowed down to one when it enters.
+#[derive(Debug, Clone)]
+struct SubsurfaceBoundary {
+    interface: Dielectric,
+    albedo: Color,
+}
+
+impl Material for SubsurfaceBoundary {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        return self.interface.scatter(r_in, rec, attenuation, scattered);
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        return self.albedo;
+    }
+
+    fn is_dispersive(&self) -> bool {
+        return true;
+    }
+}
+
+impl SubsurfaceMedium {
+    /// `albedo` is the chance of light scattering rather than being absorbed at each
+    /// collision, and `mean_free_path` the average distance between collisions
+    pub fn new(boundary: Rc<dyn Hittable>, albedo: Color, mean_free_path: Color) -> Self {
+        let mean_free_path = Color::new(
+            mean_free_path.x().max(1e-9),
+            mean_free_path.y().max(1e-9),
+            mean_free_path.z().max(1e-9),
+        );
+        let scale = mean_free_path.x().max(mean_free_path.y()).max(mean_free_path.z());
+        Self {
+            boundary: boundary,
+            albedo: albedo,
+            mean_free_path: mean_free_path,
+            spectrum: reflectance_spectrum(mean_free_path / scale),
+            spectrum_scale: scale,
+            surface: Rc::new(SubsurfaceBoundary {
+                interface: Dielectric::new(Ior::Constant(1.4)),
+                albedo: albedo,
+            }),
+            collision: Rc::new(VolumeCollision::new(albedo, Color::default(), 0.0)),
+            object_id: Cell::new(0),
+            surface_id: Cell::new(0),
+            collision_id: Cell::new(0),
+        }
+    }
+
+    pub fn with_anisotropy(mut self, g: f64) -> Self {
+        self.collision = Rc::new(VolumeCollision::new(self.albedo, Color::default(), g));
+        return self;
+    }
+
+    pub fn with_ior(mut self, ior: f64) -> Self {
+        self.surface = Rc::new(SubsurfaceBoundary {
+            interface: Dielectric::new(Ior::Constant(ior)),
+            albedo: self.albedo,
+        });
+        return self;
+    }
+
+    /// Extinction coefficient for the channel or wavelength a walk follows. Rays
+    /// without one, such as shadow rays, only need to know that they are blocked.
+    fn sigma_t(&self, r: &Ray) -> f64 {
+        let mean_free_path = match (r.channel(), r.wavelength()) {
+            (Some(channel), _) => self.mean_free_path[channel],
+            (None, Some(l)) => (self.spectrum.eval(l) * self.spectrum_scale).max(1e-9),
+            (None, None) => (self.mean_free_path.x() + self.mean_free_path.y() + self.mean_free_path.z()) / 3.0,
+        };
+        return 1.0 / mean_free_path;
+    }
+}
+
+impl Hittable for SubsurfaceMedium {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // Rays leaving the surface or a collision must not find their own starting point
+        let ray_t = Interval::new(ray_t.min.max(0.0001), ray_t.max);
+        let mut boundary_rec = HitRecord::default();
+        if !self.boundary.hit(r, Interval::new(ray_t.min, f64::INFINITY), &mut boundary_rec) {
+            return false;
+        }
+
+        if !boundary_rec.front_facing {
+            // Inside, the ray may collide with the medium before it gets out again
+            let t = sample_free_flight(r, self.sigma_t(r));
+            if t < boundary_rec.t {
+                if t >= ray_t.max {
+                    return false;
+                }
+                rec.t = t.max(ray_t.min);
+                rec.p = r.at(rec.t);
+                rec.normal = Vec3::default();
+                rec.front_facing = true;
+                rec.mat = self.collision.clone();
+                rec.light = None;
+                rec.object_id = self.object_id.get();
+                rec.material_id = self.collision_id.get();
+                return true;
+            }
+        }
+
+        if boundary_rec.t >= ray_t.max {
+            return false;
+        }
+        *rec = boundary_rec;
+        rec.mat = self.surface.clone();
+        rec.object_id = self.object_id.get();
+        rec.material_id = self.surface_id.get();
+        return true;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.boundary.bounding_box();
+    }
+
+    fn assign_ids(&self, ids: &mut SceneIds) {
+        self.object_id.set(ids.next_object());
+        self.surface_id.set(ids.next_material());
+        self.collision_id.set(ids.next_material());
+    }
+}
+
+/// Heterogeneous medium defined by a voxel grid stretched over an axis aligned box.
+/// Collisions are found with delta tracking against the grid's maximum density, which
+/// shadow rays go through as well, so they are blocked with the chance of light being
+/// absorbed or scattered on the way.
+pub struct GridMedium {
+    grid: VoxelGrid,
+    bbox: Aabb,
+    density_scale: f64,
+    majorant: f64,
+    g: f64,
+    object_id: Cell<u32>,
+    /// Shared by the collision materials, which are made per hit
+    material_id: Cell<u32>,
+}
+
+impl GridMedium {
+    pub fn new(grid: VoxelGrid, bounds: Aabb, density_scale: f64) -> Self {
+        let majorant = grid.max_density() * density_scale;
+        Self {
+            grid: grid,
+            bbox: bounds,
+            density_scale: density_scale,
+            majorant: majorant,
+            g: 0.0,
+            object_id: Cell::new(0),
+            material_id: Cell::new(0),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, bounds: Aabb, density_scale: f64) -> io::Result<Self> {
+        return Ok(Self::new(VoxelGrid::load(path)?, bounds, density_scale));
+    }
+
+    pub fn with_anisotropy(mut self, g: f64) -> Self {
+        self.g = g;
+        return self;
+    }
+
+    fn local(&self, p: Point3) -> Vec3 {
+        return Vec3::new(
+            (p.x() - self.bbox.x.min) / self.bbox.x.size(),
+            (p.y() - self.bbox.y.min) / self.bbox.y.size(),
+            (p.z() - self.bbox.z.min) / self.bbox.z.size(),
+        );
+    }
+}
+
+impl Hittable for GridMedium {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let range = match self.bbox.clip(r, ray_t) {
+            Some(range) => range,
+            None => return false,
+        };
+        if self.majorant <= 0.0 {
+            return false;
+        }
+
+        let mut t = range.min;
+        loop {
+            t += sample_free_flight(r, self.majorant);
+            if t >= range.max {
+                return false;
+            }
+
+            let p = r.at(t);
+            let voxel = self.grid.sample(self.local(p));
+            // Accept real collisions, otherwise this was a null collision and tracking continues
+            if random_f64() * self.majorant < voxel.density * self.density_scale {
+                rec.t = t;
+                rec.p = p;
+                rec.normal = Vec3::default();
+                rec.front_facing = true;
+                rec.mat = Rc::new(VolumeCollision::new(voxel.albedo, voxel.emission, self.g));
+                rec.light = None;
+                rec.object_id = self.object_id.get();
+                rec.material_id = self.material_id.get();
+                return true;
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.bbox;
+    }
+
+    fn assign_ids(&self, ids: &mut SceneIds) {
+        self.object_id.set(ids.next_object());
+        self.material_id.set(ids.next_material());
+    }
+}
+
+#[test]
+fn free_flights_are_exponential() {
+    use crate::util::seed_random;
+
+    seed_random(11);
+    // A direction of length two halves the ray parameter of every distance
+    let r = Ray::new(Point3::default(), Vec3::new(0, 0, 2));
+    let density = 0.8;
+    let n = 20000;
+    let mut mean = 0.0;
+    let mut beyond = 0;
+    for _ in 0..n {
+        let t = sample_free_flight(&r, density);
+        mean += t / n as f64;
+        beyond += (t * 2.0 > 1.5) as usize;
+    }
+    assert!((mean - 1.0 / (density * 2.0)).abs() < 0.02, "{}", mean);
+    assert!((beyond as f64 / n as f64 - (-density * 1.5f64).exp()).abs() < 0.01);
+}
+
+#[test]
+fn constant_media_transmit_exponentially() {
+    use crate::{material::HenyeyGreenstein, sphere::Sphere, util::{seed_random, vec::dot}};
+
+    seed_random(12);
+    let boundary = Rc::new(Sphere::new(Point3::new(0, 0, -3), 1.0, Rc::new(Isotropic::default())));
+    let density = 0.7;
+    let g = 0.6;
+    let medium = ConstantMedium::with_phase(boundary, density, Rc::new(HenyeyGreenstein::new(Color::new(1, 1, 1), g)));
+    let r = Ray::new(Point3::default(), Vec3::new(0, 0, -1));
+
+    let n = 20000;
+    let mut passed = 0;
+    let mut mean_cos = 0.0;
+    let mut rec = HitRecord::default();
+    for _ in 0..n {
+        if !medium.hit(&r, Interval::new(0.0, f64::INFINITY), &mut rec) {
+            passed += 1;
+            continue;
+        }
+        assert!(rec.t >= 2.0 && rec.t <= 4.0);
+        let mut attenuation = Color::default();
+        let mut scattered = Ray::default();
+        assert!(rec.mat.scatter(&r, &rec, &mut attenuation, &mut scattered));
+        mean_cos += dot(scattered.direction().to_normal(), r.direction());
+    }
+    // Two units of medium along the diameter
+    let expected = (-density * 2.0f64).exp();
+    assert!((passed as f64 / n as f64 - expected).abs() < 0.01, "{}", passed);
+    // Collisions scatter forward with the phase function's mean cosine
+    assert!((mean_cos / (n - passed) as f64 - g).abs() < 0.03);
+}
+
+#[test]
+fn fog_transmittance_matches_scattering() {
+    use crate::util::seed_random;
+
+    seed_random(13);
+    let fog = Fog::new(0.3, Color::new(1, 1, 1));
+    assert_eq!(fog.transmittance(2.5), (-0.3f64 * 2.5).exp());
+    assert_eq!(Fog::new(0.0, Color::new(1, 1, 1)).transmittance(2.5), 1.0);
+
+    let r = Ray::new(Point3::default(), Vec3::new(0, 1, 0));
+    let n = 20000;
+    let unscattered = (0..n).filter(|_| fog.scatter_before(&r, 2.5).is_none()).count();
+    assert!((unscattered as f64 / n as f64 - fog.transmittance(2.5)).abs() < 0.01);
+}
+
+#[test]
+fn subsurface_extinction_follows_channel_or_wavelength() {
+    use crate::sphere::Sphere;
+
+    let ball = Rc::new(Sphere::new(Point3::default(), 1.0, Rc::new(Isotropic::default())));
+    let mfp = Color::new(0.5, 0.25, 0.125);
+    let medium = SubsurfaceMedium::new(ball, Color::new(1, 1, 1), mfp);
+    let r = Ray::default();
+
+    for k in 0..3 {
+        assert_eq!(medium.sigma_t(&r.with_channel(Some(k)).with_wavelength(Some(500.0))), 1.0 / mfp[k]);
+    }
+    // A spectral wavelength is looked up in the spectrum even where it matches one
+    // standing in for an RGB channel
+    let spectral = medium.sigma_t(&r.with_wavelength(Some(610.0)));
+    let expected = 1.0 / (medium.spectrum.eval(610.0) * medium.spectrum_scale);
+    assert!((spectral - expected).abs() < 1e-12);
+}
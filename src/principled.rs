@@ -0,0 +1,414 @@
+use std::f64::consts::PI;
+
+use crate::{
+    hittable::HitRecord,
+    material::Material,
+    util::{
+        color::{luminance, Color},
+        microfacet::{fresnel_dielectric, ggx_d, ggx_g, gtr1_d, sample_ggx, sample_gtr1, schlick_weight},
+        onb::Onb,
+        random_f64,
+        ray::Ray,
+        vec::{dot, Vec3},
+    },
+};
+
+/// Disney's principled BSDF (Burley 2012, 2015): one material whose artist friendly
+/// parameters, all in [0, 1] apart from `ior`, blend between diffuse, metallic,
+/// glossy, coated and glass-like surfaces.
+///
+/// It is the weighted sum of a diffuse lobe with sheen, a GGX specular lobe, a GTR1
+/// clear coat and a rough GGX glass lobe that both reflects and refracts. Scattering
+/// picks one lobe by its weight, samples it, and weights the result with the density
+/// of all lobes together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Principled {
+    pub base_color: Color,
+    /// Blends from a dielectric to a metal whose reflections take the base color
+    pub metallic: f64,
+    pub roughness: f64,
+    /// Dielectric reflectance at normal incidence, 0.5 being the 4% of common materials
+    pub specular: f64,
+    /// Tints the dielectric reflections towards the base color
+    pub specular_tint: f64,
+    /// Extra grazing reflection for cloth
+    pub sheen: f64,
+    pub sheen_tint: f64,
+    /// Strength of a second, white specular lobe on top
+    pub clearcoat: f64,
+    /// Sharpness of the clear coat lobe
+    pub clearcoat_gloss: f64,
+    /// Share of the dielectric part that lets light through
+    pub transmission: f64,
+    /// Refractive index of the transmissive part
+    pub ior: f64,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Self {
+            base_color: Color::new(0.8, 0.8, 0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            ior: 1.5,
+        }
+    }
+}
+
+/// Probabilities with which `scatter` samples each lobe
+struct LobeWeights {
+    diffuse: f64,
+    specular: f64,
+    clearcoat: f64,
+    glass: f64,
+}
+
+impl Principled {
+    pub fn new(base_color: Color) -> Self {
+        Self {
+            base_color: base_color,
+            ..Default::default()
+        }
+    }
+
+    fn alpha(&self) -> f64 {
+        let r = self.roughness.clamp(0.0, 1.0);
+        return (r * r).max(1e-3);
+    }
+
+    fn clearcoat_alpha(&self) -> f64 {
+        return 0.1 + (0.001 - 0.1) * self.clearcoat_gloss.clamp(0.0, 1.0);
+    }
+
+    /// Base color normalized to a luminance of one, the hue used by the tints
+    fn tint(&self) -> Color {
+        let l = luminance(&self.base_color);
+        return if l > 0.0 { self.base_color / l } else { Color::new(1, 1, 1) };
+    }
+
+    fn diffuse_weight(&self) -> f64 {
+        return (1.0 - self.metallic) * (1.0 - self.transmission);
+    }
+
+    fn glass_weight(&self) -> f64 {
+        return (1.0 - self.metallic) * self.transmission;
+    }
+
+    fn lobe_weights(&self) -> LobeWeights {
+        let diffuse = self.diffuse_weight();
+        let specular = 1.0 - self.glass_weight();
+        let clearcoat = 0.25 * self.clearcoat;
+        let glass = self.glass_weight();
+        let total = diffuse + specular + clearcoat + glass;
+        return LobeWeights {
+            diffuse: diffuse / total,
+            specular: specular / total,
+            clearcoat: clearcoat / total,
+            glass: glass / total,
+        };
+    }
+
+    /// Index of the far side over the index of the side `rec` was hit from
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        return if rec.front_facing { self.ior } else { 1.0 / self.ior };
+    }
+
+    /// Half vector of a refraction from `wo` to `wi`, on the side of `n`
+    fn refraction_half(n: Vec3, wo: Vec3, wi: Vec3, eta: f64) -> Vec3 {
+        let h = -(wo + eta * wi).to_normal();
+        return if dot(h, n) < 0.0 { -h } else { h };
+    }
+
+    /// BSDF times the cosine for light arriving along `wi` and leaving along `wo`
+    fn f(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> Color {
+        let n = rec.normal;
+        let cos_o = dot(n, wo);
+        let cos_i = dot(n, wi);
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return Color::default();
+        }
+        let alpha = self.alpha();
+
+        if cos_i < 0.0 {
+            // Refraction through the glass lobe
+            if self.glass_weight() <= 0.0 {
+                return Color::default();
+            }
+            let eta = self.eta(rec);
+            let h = Principled::refraction_half(n, wo, wi, eta);
+            let (o_h, i_h) = (dot(wo, h), dot(wi, h));
+            if o_h <= 0.0 || i_h >= 0.0 {
+                return Color::default();
+            }
+            let fresnel = fresnel_dielectric(o_h, eta);
+            let denom = o_h + eta * i_h;
+            let value = (1.0 - fresnel) * ggx_d(dot(n, h), alpha) * ggx_g(cos_o, -cos_i, alpha) * o_h * -i_h
+                / (cos_o * denom * denom);
+            return self.glass_weight() * value * self.base_color;
+        }
+
+        let h = (wo + wi).to_normal();
+        let cos_d = dot(wi, h);
+        let cos_h = dot(n, h);
+        let mut f = Color::default();
+
+        if self.diffuse_weight() > 0.0 {
+            let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+            let fd = (1.0 + (fd90 - 1.0) * schlick_weight(cos_i)) * (1.0 + (fd90 - 1.0) * schlick_weight(cos_o));
+            let sheen_color = Color::new(1, 1, 1) + self.sheen_tint * (self.tint() - Color::new(1, 1, 1));
+            let sheen = self.sheen * schlick_weight(cos_d) * sheen_color;
+            f += self.diffuse_weight() * (fd * self.base_color / PI + sheen) * cos_i;
+        }
+
+        // Opaque dielectric and metallic reflection
+        let specular_color = Color::new(1, 1, 1) + self.specular_tint * (self.tint() - Color::new(1, 1, 1));
+        let dielectric_f0 = 0.08 * self.specular * specular_color;
+        let f0 = dielectric_f0 + self.metallic * (self.base_color - dielectric_f0);
+        let fresnel = f0 + schlick_weight(cos_d) * (Color::new(1, 1, 1) - f0);
+        let microfacet = ggx_d(cos_h, alpha) * ggx_g(cos_o, cos_i, alpha) / (4.0 * cos_o);
+        f += (1.0 - self.glass_weight()) * microfacet * fresnel;
+
+        if self.glass_weight() > 0.0 {
+            let fresnel = fresnel_dielectric(cos_d, self.eta(rec));
+            f += Color::from(self.glass_weight() * microfacet * fresnel);
+        }
+
+        if self.clearcoat > 0.0 {
+            let fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+            let coat = gtr1_d(cos_h, self.clearcoat_alpha()) * ggx_g(cos_o, cos_i, 0.25) / (4.0 * cos_o);
+            f += Color::from(0.25 * self.clearcoat * fresnel * coat);
+        }
+        return f;
+    }
+
+    fn density(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> f64 {
+        let n = rec.normal;
+        let cos_o = dot(n, wo);
+        let cos_i = dot(n, wi);
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return 0.0;
+        }
+        let w = self.lobe_weights();
+        let alpha = self.alpha();
+
+        if cos_i < 0.0 {
+            if w.glass <= 0.0 {
+                return 0.0;
+            }
+            let eta = self.eta(rec);
+            let h = Principled::refraction_half(n, wo, wi, eta);
+            let (o_h, i_h) = (dot(wo, h), dot(wi, h));
+            if o_h <= 0.0 || i_h >= 0.0 {
+                return 0.0;
+            }
+            let denom = o_h + eta * i_h;
+            let dh_dwi = eta * eta * -i_h / (denom * denom);
+            let pdf_h = ggx_d(dot(n, h), alpha) * dot(n, h);
+            return w.glass * (1.0 - fresnel_dielectric(o_h, eta)) * pdf_h * dh_dwi;
+        }
+
+        let h = (wo + wi).to_normal();
+        let cos_h = dot(n, h);
+        let o_h = dot(wo, h);
+        if o_h <= 0.0 {
+            return w.diffuse * cos_i / PI;
+        }
+        let reflect_pdf = ggx_d(cos_h, alpha) * cos_h / (4.0 * o_h);
+        let coat_pdf = gtr1_d(cos_h, self.clearcoat_alpha()) * cos_h / (4.0 * o_h);
+        let glass_reflect = if w.glass > 0.0 { fresnel_dielectric(o_h, self.eta(rec)) } else { 0.0 };
+        return w.diffuse * cos_i / PI
+            + w.specular * reflect_pdf
+            + w.glass * glass_reflect * reflect_pdf
+            + w.clearcoat * coat_pdf;
+    }
+
+    /// Picks a lobe by its weight and samples a direction from it. Samples that end up
+    /// on the wrong side of the surface for their lobe are dropped, as `density` only
+    /// counts each lobe on its own side.
+    fn sample(&self, rec: &HitRecord, wo: Vec3) -> Option<Vec3> {
+        let n = rec.normal;
+        let w = self.lobe_weights();
+        let mut u = random_f64();
+        let reflect = |h: Vec3| {
+            let wi = Vec3::reflect(-wo, h);
+            return if dot(wo, h) > 0.0 && dot(wi, n) > 0.0 { Some(wi) } else { None };
+        };
+
+        if u < w.diffuse {
+            let r1 = random_f64();
+            let phi = 2.0 * PI * random_f64();
+            let local = Vec3::new(phi.cos() * r1.sqrt(), phi.sin() * r1.sqrt(), (1.0 - r1).sqrt());
+            return Some(Onb::new(n).transform(local));
+        }
+        u -= w.diffuse;
+        if u < w.specular {
+            return reflect(sample_ggx(n, self.alpha()));
+        }
+        u -= w.specular;
+        if u < w.clearcoat {
+            return reflect(sample_gtr1(n, self.clearcoat_alpha()));
+        }
+
+        let h = sample_ggx(n, self.alpha());
+        let o_h = dot(wo, h);
+        let eta = self.eta(rec);
+        if o_h <= 0.0 {
+            return None;
+        }
+        if random_f64() < fresnel_dielectric(o_h, eta) {
+            return reflect(h);
+        }
+        let wi = Vec3::refract(-wo, h, 1.0 / eta);
+        return if dot(wi, n) < 0.0 { Some(wi) } else { None };
+    }
+}
+
+impl Material for Principled {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let wo = -r_in.direction().to_normal();
+        let wi = match self.sample(rec, wo) {
+            Some(wi) => wi.to_normal(),
+            None => return false,
+        };
+        let pdf = self.density(rec, wo, wi);
+        if pdf.is_nan() || pdf <= 0.0 {
+            return false;
+        }
+        *scattered = Ray::new_timed(rec.p, wi, r_in.time());
+        *attenuation = self.f(rec, wo, wi) / pdf;
+        return true;
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        return self.f(rec, -r_in.direction().to_normal(), wi);
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        return self.density(rec, -r_in.direction().to_normal(), wi);
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        return self.base_color;
+    }
+}
+
+#[cfg(test)]
+fn hit_from_above() -> (Ray, HitRecord) {
+    let r = Ray::new(Vec3::new(-1, 1, 0), Vec3::new(1, -1, 0));
+    let mut rec = HitRecord::default();
+    rec.set_face_normal(&r, Vec3::new(0, 1, 0));
+    return (r, rec);
+}
+
+#[cfg(test)]
+fn test_materials() -> [Principled; 3] {
+    return [
+        Principled::default(),
+        Principled {
+            metallic: 1.0,
+            roughness: 0.6,
+            ..Principled::new(Color::new(0.9, 0.6, 0.2))
+        },
+        Principled {
+            transmission: 1.0,
+            roughness: 0.5,
+            clearcoat: 1.0,
+            clearcoat_gloss: 0.0,
+            sheen: 1.0,
+            ..Default::default()
+        },
+    ];
+}
+
+#[test]
+fn sampled_directions_follow_pdf() {
+    use crate::util::seed_random;
+
+    seed_random(5);
+    let (r, rec) = hit_from_above();
+    let wo = -r.direction().to_normal();
+    // Bins of equal solid angle over the whole sphere, around the normal along y
+    let (n_cos, n_phi) = (12, 12);
+    let bin = |d: Vec3| {
+        let c = (((d.y() + 1.0) / 2.0 * n_cos as f64) as usize).min(n_cos - 1);
+        let phi = d.z().atan2(d.x()) + PI;
+        let p = ((phi / (2.0 * PI) * n_phi as f64) as usize).min(n_phi - 1);
+        c * n_phi + p
+    };
+
+    for mat in test_materials() {
+        let n = 200000;
+        let mut observed = vec![0.0; n_cos * n_phi];
+        for _ in 0..n {
+            if let Some(wi) = mat.sample(&rec, wo) {
+                observed[bin(wi.to_normal())] += 1.0;
+            }
+        }
+
+        // Integrates the density over every bin with a grid of midpoints
+        let sub = 24;
+        let (d_cos, d_phi) = (2.0 / (n_cos * sub) as f64, 2.0 * PI / (n_phi * sub) as f64);
+        let mut expected = vec![0.0; n_cos * n_phi];
+        for a in 0..n_cos * sub {
+            for b in 0..n_phi * sub {
+                let cos_theta = -1.0 + (a as f64 + 0.5) * d_cos;
+                let phi = -PI + (b as f64 + 0.5) * d_phi;
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let d = Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                expected[bin(d)] += n as f64 * mat.density(&rec, wo, d) * d_cos * d_phi;
+            }
+        }
+
+        for (o, e) in observed.iter().zip(&expected) {
+            assert!((o - e).abs() < 5.0 * e.sqrt() + 0.002 * n as f64, "{:?}: {} != {}", mat, o, e);
+        }
+    }
+}
+
+#[test]
+fn scatter_never_creates_energy() {
+    use crate::util::seed_random;
+
+    seed_random(6);
+    let (r, rec) = hit_from_above();
+    for mat in test_materials() {
+        let n = 20000;
+        let mut mean = Color::default();
+        for _ in 0..n {
+            let mut attenuation = Color::default();
+            let mut scattered = Ray::default();
+            if mat.scatter(&r, &rec, &mut attenuation, &mut scattered) {
+                mean += attenuation / n as f64;
+            }
+        }
+        assert!(mean.x() < 1.02 && mean.y() < 1.02 && mean.z() < 1.02, "{:?}: {:?}", mat, mean);
+        assert!(mean.x() > 0.3, "{:?}: {:?}", mat, mean);
+    }
+}
+
+#[test]
+fn transmission_lets_light_through() {
+    let (r, rec) = hit_from_above();
+    let glass = Principled {
+        transmission: 1.0,
+        roughness: 0.3,
+        ..Default::default()
+    };
+    let wi = Vec3::refract(r.direction().to_normal(), rec.normal, 1.0 / 1.5);
+    assert!(glass.pdf(&r, &rec, wi) > 0.0);
+    assert!(glass.eval(&r, &rec, wi).x() > 0.0);
+    assert_eq!(Principled::default().eval(&r, &rec, wi), Color::default());
+}
@@ -0,0 +1,220 @@
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Snapshot of how far a render has come, reported after every finished tile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Progressive pass being rendered, starting at 1
+    pub pass: u32,
+    /// Upper bound on the number of passes, adaptive sampling may finish sooner
+    pub passes: u32,
+    /// Tiles finished in the current pass
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+    /// Camera samples taken so far over all passes
+    pub samples: u64,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Fraction of the render done in `[0, 1]`, counting the tiles of every pass
+    pub fn fraction(&self) -> f64 {
+        let total = self.passes as usize * self.tiles_total;
+        if total == 0 {
+            return 1.0;
+        }
+        let done = (self.pass as usize).saturating_sub(1) * self.tiles_total + self.tiles_done;
+        return (done as f64 / total as f64).min(1.0);
+    }
+
+    pub fn samples_per_second(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs <= 0.0 {
+            return 0.0;
+        }
+        return self.samples as f64 / secs;
+    }
+
+    /// Time left extrapolated from the pace so far, `None` before anything is done
+    pub fn eta(&self) -> Option<Duration> {
+        let f = self.fraction();
+        if f <= 0.0 {
+            return None;
+        }
+        return Some(self.elapsed.mul_f64((1.0 - f) / f));
+    }
+}
+
+/// Receives progress updates from `Camera` while it renders
+pub trait ProgressObserver {
+    fn on_progress(&mut self, progress: &Progress);
+
+    fn on_finish(&mut self, _progress: &Progress) {}
+}
+
+/// Shared flag a caller can raise from any thread to stop a render early. The
+/// render then returns the image accumulated so far.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.flag.load(Ordering::Relaxed);
+    }
+}
+
+/// Text progress bar drawn on stderr, so it never mixes with an image on stdout
+pub struct ProgressBar {
+    width: usize,
+    last_draw: Option<Instant>,
+}
+
+impl Default for ProgressBar {
+    fn default() -> Self {
+        Self {
+            width: 40,
+            last_draw: None,
+        }
+    }
+}
+
+impl ProgressBar {
+    pub fn new() -> Self {
+        ProgressBar::default()
+    }
+
+    pub fn line(&self, progress: &Progress) -> String {
+        let f = progress.fraction();
+        let filled = (f * self.width as f64).round() as usize;
+        let eta = match progress.eta() {
+            Some(eta) => format_duration(eta),
+            None => "--:--".to_string(),
+        };
+        return format!(
+            "[{}{}] {:5.1}% pass {}/{} {:.2} Msamples/s ETA {}",
+            "#".repeat(filled),
+            " ".repeat(self.width - filled),
+            100.0 * f,
+            progress.pass,
+            progress.passes,
+            progress.samples_per_second() / 1e6,
+            eta
+        );
+    }
+
+    fn draw(&mut self, progress: &Progress) {
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r{}", self.line(progress));
+        let _ = stderr.flush();
+        self.last_draw = Some(Instant::now());
+    }
+}
+
+impl ProgressObserver for ProgressBar {
+    fn on_progress(&mut self, progress: &Progress) {
+        // Redrawing on every tile would spend more time in the terminal than rendering
+        if let Some(last) = self.last_draw {
+            if last.elapsed() < Duration::from_millis(100) {
+                return;
+            }
+        }
+        self.draw(progress);
+    }
+
+    fn on_finish(&mut self, progress: &Progress) {
+        self.draw(progress);
+        eprintln!();
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs >= 3600 {
+        return format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+    }
+    return format!("{}:{:02}", secs / 60, secs % 60);
+}
+
+#[test]
+fn progress_fraction_and_eta() {
+    let p = Progress {
+        pass: 2,
+        passes: 4,
+        tiles_done: 5,
+        tiles_total: 10,
+        samples: 3000,
+        elapsed: Duration::from_secs(3),
+    };
+    assert_eq!(p.fraction(), 0.375);
+    assert_eq!(p.samples_per_second(), 1000.0);
+    assert_eq!(p.eta(), Some(Duration::from_secs(5)));
+
+    let bar = ProgressBar::new();
+    assert!(bar.line(&p).contains(" 37.5% pass 2/4"));
+    assert!(bar.line(&p).ends_with("ETA 0:05"));
+}
+
+#[test]
+fn cancel_token_is_shared_between_clones() {
+    let token = CancelToken::new();
+    let remote = token.clone();
+    assert!(!token.is_cancelled());
+
+    std::thread::spawn(move || remote.cancel()).join().unwrap();
+    assert!(token.is_cancelled());
+}
+
+#[cfg(test)]
+struct Recorder(std::rc::Rc<std::cell::RefCell<Vec<Progress>>>);
+
+#[cfg(test)]
+impl ProgressObserver for Recorder {
+    fn on_progress(&mut self, progress: &Progress) {
+        self.0.borrow_mut().push(*progress);
+    }
+
+    fn on_finish(&mut self, progress: &Progress) {
+        self.0.borrow_mut().push(*progress);
+    }
+}
+
+#[test]
+fn camera_reports_every_tile_and_honours_cancel() {
+    use crate::{camera::Camera, hittable::HittableList};
+
+    let reports = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let mut cam = Camera::new();
+    cam.image_width = 4;
+    cam.samples_per_pixel = 4;
+    cam.pass_samples = 2;
+    cam.tile_size = 2;
+    cam.observer = Some(Box::new(Recorder(reports.clone())));
+    cam.render_image(&HittableList::new());
+
+    let reports = reports.borrow();
+    assert_eq!(reports.len(), 2 * 4 + 1);
+    assert_eq!(reports[0].fraction(), 0.125);
+    assert_eq!(reports.last().unwrap().fraction(), 1.0);
+    assert_eq!(reports.last().unwrap().samples, 4 * 4 * 4);
+
+    cam.observer = None;
+    cam.cancel.cancel();
+    let img = cam.render_image(&HittableList::new());
+    assert!(img.data().iter().all(|c| c.near_zero()));
+}
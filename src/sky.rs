@@ -0,0 +1,340 @@
+use std::f64::consts::PI;
+
+use crate::{
+    environment::{Environment, EnvironmentSample},
+    util::{
+        random_f64,
+        color::Color,
+        onb::Onb,
+        vec::{dot, Vec3},
+    },
+};
+
+/// Luminance scale bringing a clear midday zenith (a few kcd/m^2) to roughly 1
+const SKY_LUMINANCE_SCALE: f64 = 1.0 / 8.0;
+
+/// Mean angular radius of the sun seen from earth, in radians
+pub const SUN_ANGULAR_RADIUS: f64 = 0.00465;
+
+/// Sun direction given as angles above the horizon and clockwise from north, in radians.
+/// The scene is y up with north along -z and east along +x.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SunPosition {
+    pub elevation: f64,
+    pub azimuth: f64,
+}
+
+/// Preetham analytic daylight model with a sampled sun disc
+#[derive(Debug, Clone)]
+pub struct PhysicalSky {
+    sun_dir: Vec3,
+    turbidity: f64,
+    sun_radius: f64,
+    sun_intensity: f64,
+    intensity: f64,
+    ground: Color,
+    perez_y: [f64; 5],
+    perez_x: [f64; 5],
+    perez_yy: [f64; 5],
+    zenith: Vec3,
+    sun_radiance: Color,
+}
+
+impl SunPosition {
+    pub fn new(elevation: f64, azimuth: f64) -> Self {
+        Self {
+            elevation: elevation,
+            azimuth: azimuth,
+        }
+    }
+
+    /// Approximate solar position for a day of the year (1 to 365), local solar
+    /// time in hours and latitude in degrees
+    pub fn from_time(day_of_year: u32, solar_hour: f64, latitude: f64) -> Self {
+        let lat = latitude.to_radians();
+        let declination =
+            23.44_f64.to_radians() * (2.0 * PI * (284.0 + day_of_year as f64) / 365.0).sin();
+        let hour_angle = (15.0 * (solar_hour - 12.0)).to_radians();
+
+        let sin_elevation = lat.sin() * declination.sin()
+            + lat.cos() * declination.cos() * hour_angle.cos();
+        let elevation = sin_elevation.clamp(-1.0, 1.0).asin();
+
+        let cos_azimuth = (declination.sin() - sin_elevation * lat.sin())
+            / (elevation.cos() * lat.cos()).max(1e-9);
+        let mut azimuth = cos_azimuth.clamp(-1.0, 1.0).acos();
+        if hour_angle > 0.0 {
+            azimuth = 2.0 * PI - azimuth;
+        }
+
+        return Self::new(elevation, azimuth);
+    }
+
+    pub fn direction(&self) -> Vec3 {
+        let (se, ce) = self.elevation.sin_cos();
+        let (sa, ca) = self.azimuth.sin_cos();
+        return Vec3::new(ce * sa, se, -ce * ca);
+    }
+}
+
+impl PhysicalSky {
+    pub fn new(sun_dir: Vec3, turbidity: f64) -> Self {
+        let mut sky = Self {
+            sun_dir: sun_dir.to_normal(),
+            turbidity: turbidity.max(1.0),
+            sun_radius: SUN_ANGULAR_RADIUS,
+            sun_intensity: 1.0,
+            intensity: 1.0,
+            ground: Color::new(0.1, 0.1, 0.1),
+            perez_y: [0.0; 5],
+            perez_x: [0.0; 5],
+            perez_yy: [0.0; 5],
+            zenith: Vec3::default(),
+            sun_radiance: Color::default(),
+        };
+        sky.precompute();
+        return sky;
+    }
+
+    pub fn from_sun_position(sun: SunPosition, turbidity: f64) -> Self {
+        return Self::new(sun.direction(), turbidity);
+    }
+
+    pub fn with_sun_radius(mut self, radians: f64) -> Self {
+        self.sun_radius = radians.max(1e-6);
+        self.precompute();
+        return self;
+    }
+
+    /// Scales the sun disc's radiance relative to a physically plausible default
+    pub fn with_sun_intensity(mut self, intensity: f64) -> Self {
+        self.sun_intensity = intensity;
+        self.precompute();
+        return self;
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        return self;
+    }
+
+    pub fn with_ground(mut self, ground: Color) -> Self {
+        self.ground = ground;
+        return self;
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        return self.sun_dir;
+    }
+
+    fn precompute(&mut self) {
+        let t = self.turbidity;
+        self.perez_y = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        self.perez_x = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        self.perez_yy = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        // The analytic fits only hold for a sun above the horizon
+        let theta_s = self.sun_dir.y().clamp(0.0, 1.0).acos().min(PI / 2.0 - 0.001);
+        let t2 = t * t;
+        let ts2 = theta_s * theta_s;
+        let ts3 = ts2 * theta_s;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = t2 * (0.00166 * ts3 - 0.00375 * ts2 + 0.00209 * theta_s)
+            + t * (-0.02903 * ts3 + 0.06377 * ts2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * ts3 - 0.21196 * ts2 + 0.06052 * theta_s + 0.25886);
+        let zenith_yy = t2 * (0.00275 * ts3 - 0.00610 * ts2 + 0.00317 * theta_s)
+            + t * (-0.04214 * ts3 + 0.08970 * ts2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * ts3 - 0.26756 * ts2 + 0.06670 * theta_s + 0.26688);
+
+        self.zenith = Vec3::new(
+            zenith_y / perez(&self.perez_y, 0.0, theta_s),
+            zenith_x / perez(&self.perez_x, 0.0, theta_s),
+            zenith_yy / perez(&self.perez_yy, 0.0, theta_s),
+        );
+        self.sun_radiance = self.sun_intensity * sun_transmittance(theta_s, t) * sun_disc_radiance(self.sun_radius);
+    }
+
+    fn sky_value(&self, dir: Vec3) -> Color {
+        if dir.y() <= 0.0 {
+            return self.ground;
+        }
+
+        let cos_theta = dir.y().max(0.001);
+        let gamma = dot(dir, self.sun_dir).clamp(-1.0, 1.0).acos();
+        let theta = cos_theta.acos();
+
+        let lum = self.zenith.x() * perez(&self.perez_y, theta, gamma);
+        let x = self.zenith.y() * perez(&self.perez_x, theta, gamma);
+        let y = self.zenith.z() * perez(&self.perez_yy, theta, gamma);
+
+        return xyy_to_rgb(x, y, lum * SKY_LUMINANCE_SCALE);
+    }
+
+    fn in_sun_disc(&self, dir: Vec3) -> bool {
+        return dot(dir, self.sun_dir) >= self.sun_radius.cos();
+    }
+
+    fn sun_cone_pdf(&self) -> f64 {
+        return 1.0 / (2.0 * PI * (1.0 - self.sun_radius.cos()));
+    }
+
+    /// Fraction of light samples aimed at the sun disc, zero once it has set
+    fn sun_sample_probability(&self) -> f64 {
+        return if self.sun_dir.y() > -self.sun_radius.sin() { 0.5 } else { 0.0 };
+    }
+}
+
+impl Environment for PhysicalSky {
+    fn value(&self, dir: Vec3) -> Color {
+        let d = dir.to_normal();
+        let mut c = self.sky_value(d);
+        // Below the horizon the ground hides the sun
+        if d.y() > 0.0 && self.in_sun_disc(d) {
+            c += self.sun_radiance;
+        }
+        return self.intensity * c;
+    }
+
+    fn sample(&self) -> EnvironmentSample {
+        let p_sun = self.sun_sample_probability();
+        let dir = if random_f64() < p_sun {
+            let cos_theta = 1.0 - random_f64() * (1.0 - self.sun_radius.cos());
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * random_f64();
+            Onb::new(self.sun_dir).transform(Vec3::new(
+                sin_theta * phi.cos(),
+                sin_theta * phi.sin(),
+                cos_theta,
+            ))
+        } else {
+            Vec3::random_normal()
+        };
+
+        EnvironmentSample {
+            dir: dir,
+            value: self.value(dir),
+            pdf: self.pdf(dir),
+        }
+    }
+
+    fn pdf(&self, dir: Vec3) -> f64 {
+        let p_sun = self.sun_sample_probability();
+        let mut pdf = (1.0 - p_sun) / (4.0 * PI);
+        if self.in_sun_disc(dir.to_normal()) {
+            pdf += p_sun * self.sun_cone_pdf();
+        }
+        return pdf;
+    }
+}
+
+/// Perez et al. all weather luminance distribution
+#[inline]
+fn perez(coeffs: &[f64; 5], theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = *coeffs;
+    let cos_gamma = gamma.cos();
+    return (1.0 + a * (b / theta.cos().max(0.001)).exp())
+        * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma);
+}
+
+fn xyy_to_rgb(x: f64, y: f64, lum: f64) -> Color {
+    if y <= 0.0 {
+        return Color::default();
+    }
+    let cx = x * lum / y;
+    let cz = (1.0 - x - y) * lum / y;
+    let r = 3.2404542 * cx - 1.5371385 * lum - 0.4985314 * cz;
+    let g = -0.9692660 * cx + 1.8760108 * lum + 0.0415560 * cz;
+    let b = 0.0556434 * cx - 0.2040259 * lum + 1.0572252 * cz;
+    return Color::new(r.max(0.0), g.max(0.0), b.max(0.0));
+}
+
+/// Rayleigh and aerosol transmittance of sunlight through the atmosphere at
+/// representative red, green and blue wavelengths
+fn sun_transmittance(theta_s: f64, turbidity: f64) -> Color {
+    let relative_air_mass =
+        1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).max(1e-3).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let alpha = 1.3;
+
+    let channel = |lambda_um: f64| -> f64 {
+        let rayleigh = (-0.008735 * lambda_um.powf(-4.08) * relative_air_mass).exp();
+        let aerosol = (-beta * lambda_um.powf(-alpha) * relative_air_mass).exp();
+        return rayleigh * aerosol;
+    };
+    return Color::new(channel(0.65), channel(0.55), channel(0.45));
+}
+
+/// Disc radiance such that the sun's irradiance stays fixed as its apparent size changes,
+/// keeping direct sunlight around twenty times brighter than the midday sky
+fn sun_disc_radiance(radius: f64) -> f64 {
+    let solid_angle = 2.0 * PI * (1.0 - radius.cos());
+    return 20.0 / solid_angle;
+}
+
+#[test]
+fn sun_position_equinox_noon() {
+    let sun = SunPosition::from_time(80, 12.0, 0.0);
+    assert!(sun.elevation > 89.0_f64.to_radians());
+
+    let morning = SunPosition::from_time(172, 8.0, 48.0);
+    let evening = SunPosition::from_time(172, 16.0, 48.0);
+    assert!((morning.elevation - evening.elevation).abs() < 1e-9);
+    assert!(morning.direction().x() > 0.0);
+    assert!(evening.direction().x() < 0.0);
+}
+
+#[test]
+fn physical_sky_brighter_towards_sun() {
+    use crate::util::color::luminance;
+
+    let sky = PhysicalSky::from_sun_position(SunPosition::new(0.5, 1.0), 3.0);
+    let sun = sky.sun_direction();
+    let near_sun = (sun + Vec3::new(0.0, 0.1, 0.0)).to_normal();
+    let away = Vec3::new(-sun.x(), sun.y(), -sun.z());
+
+    assert!(luminance(&sky.value(near_sun)) > luminance(&sky.value(away)));
+    assert!(luminance(&sky.value(sun)) > 100.0 * luminance(&sky.value(near_sun)));
+}
+
+#[test]
+fn physical_sky_sample_matches_pdf() {
+    let sky = PhysicalSky::new(Vec3::new(0.2, 0.6, -0.4), 2.5);
+    let mut sun_hits = 0;
+    for _ in 0..200 {
+        let s = sky.sample();
+        assert!((sky.pdf(s.dir) - s.pdf).abs() < 1e-9 * s.pdf);
+        if sky.in_sun_disc(s.dir) {
+            sun_hits += 1;
+        }
+    }
+    assert!(sun_hits > 50);
+}
+
+#[test]
+fn set_sun_is_hidden_by_the_ground() {
+    let sky = PhysicalSky::from_sun_position(SunPosition::new(-0.1, 1.0), 3.0);
+    let sun = sky.sun_direction();
+    assert!(sky.in_sun_disc(sun));
+    assert_eq!(sky.value(sun), sky.intensity * sky.ground);
+    assert_eq!(sky.sun_sample_probability(), 0.0);
+}
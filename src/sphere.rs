@@ -1,38 +1,91 @@
-use std::rc::Rc;
+use std::{cell::Cell, f64::consts::PI, rc::Rc};
 
 use num::{FromPrimitive, ToPrimitive};
 
-use crate::hittable::{HitRecord, Hittable};
-use crate::material::{Lambertian, Material};
-use crate::util::color::Color;
+use crate::hittable::{passes_opacity, HitRecord, Hittable, SceneIds};
+use crate::light::Light;
+use crate::material::Material;
+use crate::util::aabb::Aabb;
 use crate::util::interval::Interval;
+use crate::util::ray::Ray;
 use crate::util::vec::{dot, Point3, Vec3};
 
 #[derive(Debug, Clone)]
 pub struct Sphere {
-    center: Point3,
+    /// Center at time 0, moving along `direction` to reach its end position at time 1
+    center: Ray,
     radius: f64,
-    mat: Rc<dyn Material>
+    mat: Rc<dyn Material>,
+    bbox: Aabb,
+    area_light: Option<Rc<dyn Light>>,
+    object_id: Cell<u32>,
+    material_id: Cell<u32>,
 }
 
 impl Sphere {
     pub fn new<T>(center: Point3, radius: T, mat: Rc<dyn Material>) -> Self where T: ToPrimitive+FromPrimitive {
+        return Self::new_moving(center, center, radius, mat);
+    }
+
+    pub fn new_moving<T>(center1: Point3, center2: Point3, radius: T, mat: Rc<dyn Material>) -> Self where T: ToPrimitive+FromPrimitive {
+        let r = radius.to_f64().unwrap().max(0.0);
+        let rvec = Vec3::from(r);
+        let box1 = Aabb::from_points(center1 - rvec, center1 + rvec);
+        let box2 = Aabb::from_points(center2 - rvec, center2 + rvec);
+
         Self {
-            center: center,
-            radius: radius.to_f64().unwrap().max(0.0),
-            mat: mat
+            center: Ray::new(center1, center2 - center1),
+            radius: r,
+            mat: mat,
+            bbox: Aabb::enclosing(&box1, &box2),
+            area_light: None,
+            object_id: Cell::new(0),
+            material_id: Cell::new(0),
         }
     }
+
+    pub fn with_area_light(mut self, light: Rc<dyn Light>) -> Self {
+        self.area_light = Some(light);
+        return self;
+    }
+
+    pub fn center(&self, time: f64) -> Point3 {
+        return self.center.at(time);
+    }
+
+    /// Derivatives of the surface point with unit offset `n` from the center along the
+    /// texture coordinates of `uv`
+    fn tangents(&self, n: Vec3) -> (Vec3, Vec3) {
+        let r = self.radius;
+        let dpdu = 2.0 * PI * r * Vec3::new(n.z(), 0.0, -n.x());
+        let sin_theta = (n.x() * n.x() + n.z() * n.z()).sqrt();
+        if sin_theta < 1e-9 {
+            // The poles, where every direction along the surface is a tangent
+            return (Vec3::new(2.0 * PI * r, 0.0, 0.0), Vec3::new(0.0, 0.0, PI * r));
+        }
+        let cos_theta = -n.y();
+        let dpdv = PI * r * Vec3::new(n.x() * cos_theta / sin_theta, sin_theta, n.z() * cos_theta / sin_theta);
+        return (dpdu, dpdv);
+    }
+
+    /// Texture coordinates of the point `p` on the unit sphere, `u` running around
+    /// the y axis from -x and `v` from the bottom pole to the top one
+    fn uv(p: Point3) -> (f64, f64) {
+        let theta = (-p.y()).clamp(-1.0, 1.0).acos();
+        let phi = (-p.z()).atan2(p.x()) + PI;
+        return (phi / (2.0 * PI), theta / PI);
+    }
 }
 
 impl Hittable for Sphere {
     fn hit(
         &self,
-        r: &crate::util::ray::Ray,
+        r: &Ray,
         ray_t: Interval,
         rec: &mut HitRecord,
     ) -> bool {
-        let oc: Vec3 = self.center - r.origin();
+        let current_center = self.center(r.time());
+        let oc: Vec3 = current_center - r.origin();
         let a = r.direction().length_squared();
         let h = dot(r.direction(), oc);
         let c = oc.length_squared() - self.radius * self.radius;
@@ -44,20 +97,108 @@ impl Hittable for Sphere {
 
         let sqrtd = discr.sqrt();
 
-        let mut root = (h - sqrtd) / a;
-        if !ray_t.surrounds(root) {
-            root = (h + sqrtd) / a;
+        // The far side is still visible through cut out parts of the near one. `rec` is
+        // only written once a root is kept, as a BVH node hands it on to its other child
+        // with the closer hit already in it.
+        for root in [(h - sqrtd) / a, (h + sqrtd) / a] {
             if !ray_t.surrounds(root) {
-                return false;
+                continue;
             }
+
+            let mut candidate = rec.clone();
+            candidate.t = root;
+            candidate.p = r.at(root);
+            let outward_normal = (candidate.p - current_center) / self.radius;
+            candidate.set_face_normal(r, outward_normal);
+            (candidate.u, candidate.v) = Sphere::uv(outward_normal);
+            (candidate.dpdu, candidate.dpdv) = self.tangents(outward_normal);
+            if !passes_opacity(&*self.mat, &candidate) {
+                continue;
+            }
+            candidate.mat = self.mat.clone();
+            candidate.light = self.area_light.clone();
+            candidate.object_id = self.object_id.get();
+            candidate.material_id = self.material_id.get();
+            *rec = candidate;
+            return true;
         }
 
-        rec.t = root;
-        rec.p = r.at(rec.t);
-        let outward_normal = (rec.p - self.center) / self.radius;
-        rec.set_face_normal(r, outward_normal);
-        rec.mat = self.mat.clone();
+        return false;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.bbox;
+    }
 
-        return true;
+    fn assign_ids(&self, ids: &mut SceneIds) {
+        self.object_id.set(ids.next_object());
+        self.material_id.set(ids.material(&self.mat));
     }
 }
+
+#[test]
+fn sphere_uv_wraps_around_y() {
+    let (u, v) = Sphere::uv(Point3::new(-1, 0, 0));
+    assert!((u - 0.0).abs() < 1e-12 || (u - 1.0).abs() < 1e-12);
+    assert!((v - 0.5).abs() < 1e-12);
+    assert_eq!(Sphere::uv(Point3::new(0, 1, 0)).1, 1.0);
+    assert_eq!(Sphere::uv(Point3::new(0, -1, 0)).1, 0.0);
+    assert!((Sphere::uv(Point3::new(1, 0, 0)).0 - 0.5).abs() < 1e-12);
+}
+
+#[test]
+fn sphere_tangents_follow_uv() {
+    use crate::material::Lambertian;
+
+    let sphere = Sphere::new(Point3::new(0, 0, 0), 2.0, Rc::new(Lambertian::default()));
+    let n = Vec3::new(0.3, 0.5, -0.2).to_normal();
+    let (u, v) = Sphere::uv(n);
+    let (dpdu, dpdv) = sphere.tangents(n);
+    assert!(dot(dpdu, n).abs() < 1e-12 && dot(dpdv, n).abs() < 1e-12);
+
+    // Stepping along the derivatives moves the texture coordinates by the step
+    let h: f64 = 1e-6;
+    let (u1, _) = Sphere::uv((n * 2.0 + dpdu * h).to_normal());
+    let (_, v1) = Sphere::uv((n * 2.0 + dpdv * h).to_normal());
+    assert!(((u1 - u) / h - 1.0).abs() < 1e-3);
+    assert!(((v1 - v) / h - 1.0).abs() < 1e-3);
+}
+
+#[test]
+fn cutouts_let_rays_through() {
+    use crate::{
+        material::{Cutout, Lambertian},
+        texture::SolidColor,
+        util::{color::Color, seed_random},
+    };
+
+    let cutout = |alpha: f64| -> Rc<dyn Material> {
+        let opacity = Rc::new(SolidColor::new(Color::from(alpha)));
+        return Rc::new(Cutout::new(Rc::new(Lambertian::default()), opacity));
+    };
+    let r = Ray::new(Point3::new(0, 0, 0), Vec3::new(0, 0, -1));
+    let hits = |mat: Rc<dyn Material>| {
+        let sphere = Sphere::new(Point3::new(0, 0, -2), 0.5, mat);
+        let mut rec = HitRecord::default();
+        let mut count = 0;
+        let mut far = 0;
+        for _ in 0..4000 {
+            if sphere.hit(&r, Interval::new(0, f64::INFINITY), &mut rec) {
+                count += 1;
+                far += (rec.t > 2.0) as usize;
+            }
+        }
+        return (count, far);
+    };
+
+    seed_random(2);
+    assert_eq!(hits(cutout(1.0)), (4000, 0));
+    assert_eq!(hits(cutout(0.0)), (0, 0));
+    // A ray gets through half opaque near and far sides a quarter of the time
+    let (count, far) = hits(cutout(0.5));
+    assert!((count as f64 / 4000.0 - 0.75).abs() < 0.03, "{}", count);
+    assert!((far as f64 / 4000.0 - 0.25).abs() < 0.03, "{}", far);
+
+    let solid = Cutout::new(Rc::new(Lambertian::default()), Rc::new(SolidColor::new(Color::from(0.6))));
+    assert_eq!(hits(Rc::new(solid.with_threshold(0.5))), (4000, 0));
+}
@@ -0,0 +1,115 @@
+use std::{fmt::Debug, rc::Rc};
+
+use crate::util::{color::Color, perlin::Perlin, vec::Point3};
+
+/// Spatially varying value looked up at a hit's surface coordinates `u`, `v` and
+/// world position `p`
+pub trait Texture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+}
+
+impl Debug for dyn Texture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad("Texture")
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct SolidColor {
+    albedo: Color,
+}
+
+impl SolidColor {
+    pub fn new(albedo: Color) -> Self {
+        Self { albedo: albedo }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Color {
+        return self.albedo;
+    }
+}
+
+/// Alternates between two textures in a 3D checkerboard of cubes `scale` wide
+#[derive(Debug, Clone)]
+pub struct CheckerTexture {
+    inv_scale: f64,
+    even: Rc<dyn Texture>,
+    odd: Rc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: f64, even: Rc<dyn Texture>, odd: Rc<dyn Texture>) -> Self {
+        Self {
+            inv_scale: 1.0 / scale,
+            even: even,
+            odd: odd,
+        }
+    }
+
+    pub fn from_colors(scale: f64, even: Color, odd: Color) -> Self {
+        return Self::new(scale, Rc::new(SolidColor::new(even)), Rc::new(SolidColor::new(odd)));
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        let cell = (self.inv_scale * p.x()).floor()
+            + (self.inv_scale * p.y()).floor()
+            + (self.inv_scale * p.z()).floor();
+        return if cell.rem_euclid(2.0) == 0.0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        };
+    }
+}
+
+/// Gray Perlin turbulence in [0, 1], with features about `1 / scale` apart
+#[derive(Debug, Clone)]
+pub struct NoiseTexture {
+    noise: Perlin,
+    scale: f64,
+    octaves: usize,
+}
+
+impl NoiseTexture {
+    pub fn new(scale: f64) -> Self {
+        Self::seeded(scale, 0)
+    }
+
+    /// Noise with a different pattern for each seed
+    pub fn seeded(scale: f64, seed: u64) -> Self {
+        Self {
+            noise: Perlin::new(seed),
+            scale: scale,
+            octaves: 7,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let t = self.noise.turbulence(self.scale * p, self.octaves);
+        return Color::from(t.clamp(0.0, 1.0));
+    }
+}
+
+#[test]
+fn checker_alternates_between_cells() {
+    let checker = CheckerTexture::from_colors(0.5, Color::new(1, 1, 1), Color::default());
+    assert_eq!(checker.value(0.0, 0.0, Point3::new(0.1, 0.1, 0.1)), Color::new(1, 1, 1));
+    assert_eq!(checker.value(0.0, 0.0, Point3::new(0.6, 0.1, 0.1)), Color::default());
+    assert_eq!(checker.value(0.0, 0.0, Point3::new(-0.1, 0.1, 0.1)), Color::default());
+    assert_eq!(checker.value(0.0, 0.0, Point3::new(0.6, 0.6, 0.1)), Color::new(1, 1, 1));
+}
+
+#[test]
+fn noise_stays_in_unit_range() {
+    let noise = NoiseTexture::new(4.0);
+    for k in 0..200 {
+        let v = noise.value(0.0, 0.0, Point3::new(0.13 * k as f64, 0.7, -0.2)).x();
+        assert!((0.0..=1.0).contains(&v));
+    }
+}
@@ -0,0 +1,167 @@
+/// Axis aligned pixel rectangle covering columns `x0..x1` and rows `y0..y1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Rect {
+    pub fn new(x0: usize, y0: usize, x1: usize, y1: usize) -> Self {
+        Self {
+            x0: x0,
+            y0: y0,
+            x1: x1.max(x0),
+            y1: y1.max(y0),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        return self.x1 - self.x0;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.y1 - self.y0;
+    }
+
+    pub fn area(&self) -> usize {
+        return self.width() * self.height();
+    }
+
+    /// Grows the rectangle by `by` pixels on every side, staying within `bounds`
+    pub fn expand(&self, by: usize, bounds: &Rect) -> Rect {
+        let grown = Rect::new(self.x0.saturating_sub(by), self.y0.saturating_sub(by), self.x1 + by, self.y1 + by);
+        return grown.intersect(bounds);
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        return Rect::new(
+            self.x0.max(other.x0),
+            self.y0.max(other.y0),
+            self.x1.min(other.x1),
+            self.y1.min(other.y1),
+        );
+    }
+}
+
+/// Part of the image to render, either in pixels or as fractions of the frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CropWindow {
+    Pixels(Rect),
+    /// Corners in [0, 1] from the top left of the frame, rounded outwards to whole pixels
+    Normalized { x0: f64, y0: f64, x1: f64, y1: f64 },
+}
+
+impl CropWindow {
+    /// The pixels of a `width` by `height` frame covered by the window
+    pub fn resolve(&self, width: usize, height: usize) -> Rect {
+        let frame = Rect::new(0, 0, width, height);
+        let rect = match *self {
+            CropWindow::Pixels(rect) => rect,
+            CropWindow::Normalized { x0, y0, x1, y1 } => {
+                let w = width as f64;
+                let h = height as f64;
+                Rect::new(
+                    (x0.clamp(0.0, 1.0) * w).floor() as usize,
+                    (y0.clamp(0.0, 1.0) * h).floor() as usize,
+                    (x1.clamp(0.0, 1.0) * w).ceil() as usize,
+                    (y1.clamp(0.0, 1.0) * h).ceil() as usize,
+                )
+            }
+        };
+        return rect.intersect(&frame);
+    }
+}
+
+/// What a cropped render produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CropOutput {
+    /// An image the size of the crop window
+    Cropped,
+    /// An image of the full frame, black outside the crop window
+    #[default]
+    FullFrame,
+}
+
+/// Splits a region into square tiles walked row by row from the top left.
+/// Tiles on the right and bottom edges are clipped to the region.
+#[derive(Debug, Clone, Copy)]
+pub struct TileScheduler {
+    region: Rect,
+    tile_size: usize,
+}
+
+impl TileScheduler {
+    pub fn new(region: Rect, tile_size: usize) -> Self {
+        Self {
+            region: region,
+            tile_size: tile_size.max(1),
+        }
+    }
+
+    pub fn tiles_x(&self) -> usize {
+        return self.region.width().div_ceil(self.tile_size);
+    }
+
+    pub fn tiles_y(&self) -> usize {
+        return self.region.height().div_ceil(self.tile_size);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.tiles_x() * self.tiles_y();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    pub fn tile(&self, index: usize) -> Rect {
+        let tx = index % self.tiles_x();
+        let ty = index / self.tiles_x();
+        let x0 = self.region.x0 + tx * self.tile_size;
+        let y0 = self.region.y0 + ty * self.tile_size;
+        return Rect::new(x0, y0, x0 + self.tile_size, y0 + self.tile_size).intersect(&self.region);
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = Rect> + '_ {
+        return (0..self.len()).map(move |i| self.tile(i));
+    }
+}
+
+#[test]
+fn crop_window_resolves_inside_frame() {
+    let normalized = CropWindow::Normalized {
+        x0: 0.25,
+        y0: 0.1,
+        x1: 0.5,
+        y1: 1.5,
+    };
+    assert_eq!(normalized.resolve(100, 50), Rect::new(25, 5, 50, 50));
+
+    let pixels = CropWindow::Pixels(Rect::new(90, 10, 120, 20));
+    assert_eq!(pixels.resolve(100, 50), Rect::new(90, 10, 100, 20));
+
+    let frame = Rect::new(0, 0, 100, 50);
+    assert_eq!(Rect::new(1, 10, 98, 20).expand(2, &frame), Rect::new(0, 8, 100, 22));
+}
+
+#[test]
+fn tiles_cover_region_once() {
+    let region = Rect::new(3, 2, 20, 9);
+    let scheduler = TileScheduler::new(region, 8);
+    assert_eq!((scheduler.tiles_x(), scheduler.tiles_y()), (3, 1));
+
+    let mut covered = vec![0; 32 * 16];
+    for tile in scheduler.tiles() {
+        assert!(tile.width() <= 8 && tile.height() <= 8);
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                covered[y * 32 + x] += 1;
+            }
+        }
+    }
+    let total: usize = scheduler.tiles().map(|t| t.area()).sum();
+    assert_eq!(total, region.area());
+    assert!(covered.iter().all(|&c| c <= 1));
+}
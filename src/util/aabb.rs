@@ -0,0 +1,139 @@
+use crate::util::{interval::Interval, ray::Ray, vec::Point3};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Aabb {
+    pub fn new(x: Interval, y: Interval, z: Interval) -> Self {
+        let mut bbox = Self { x: x, y: y, z: z };
+        bbox.pad_to_minimums();
+        return bbox;
+    }
+
+    pub fn from_points(a: Point3, b: Point3) -> Self {
+        return Self::new(
+            Interval::new(a.x().min(b.x()), a.x().max(b.x())),
+            Interval::new(a.y().min(b.y()), a.y().max(b.y())),
+            Interval::new(a.z().min(b.z()), a.z().max(b.z())),
+        );
+    }
+
+    pub fn enclosing(a: &Aabb, b: &Aabb) -> Self {
+        Self {
+            x: Interval::enclosing(&a.x, &b.x),
+            y: Interval::enclosing(&a.y, &b.y),
+            z: Interval::enclosing(&a.z, &b.z),
+        }
+    }
+
+    pub const fn empty() -> Self {
+        Self {
+            x: Interval::empty(),
+            y: Interval::empty(),
+            z: Interval::empty(),
+        }
+    }
+
+    pub fn axis_interval(&self, n: usize) -> &Interval {
+        return match n {
+            1 => &self.y,
+            2 => &self.z,
+            _ => &self.x,
+        };
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        if self.x.size() > self.y.size() {
+            return if self.x.size() > self.z.size() { 0 } else { 2 };
+        }
+        return if self.y.size() > self.z.size() { 1 } else { 2 };
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        return Point3::new(
+            0.5 * (self.x.min + self.x.max),
+            0.5 * (self.y.min + self.y.max),
+            0.5 * (self.z.min + self.z.max),
+        );
+    }
+
+    pub fn hit(&self, r: &Ray, ray_t: Interval) -> bool {
+        return self.clip(r, ray_t).is_some();
+    }
+
+    /// Returns the part of `ray_t` over which the ray is inside the box
+    pub fn clip(&self, r: &Ray, ray_t: Interval) -> Option<Interval> {
+        let origin = r.origin();
+        let dir = r.direction();
+        let mut t = ray_t;
+
+        for axis in 0..3 {
+            let ax = self.axis_interval(axis);
+            let adinv = 1.0 / dir[axis];
+
+            let t0 = (ax.min - origin[axis]) * adinv;
+            let t1 = (ax.max - origin[axis]) * adinv;
+
+            if t0 < t1 {
+                t.min = t.min.max(t0);
+                t.max = t.max.min(t1);
+            } else {
+                t.min = t.min.max(t1);
+                t.max = t.max.min(t0);
+            }
+
+            if t.max <= t.min {
+                return None;
+            }
+        }
+        return Some(t);
+    }
+
+    fn pad_to_minimums(&mut self) {
+        // Keep flat boxes (e.g. around axis aligned quads) from degenerating
+        let delta = 0.0001;
+        if self.x.size() < delta {
+            self.x = self.x.expand(delta);
+        }
+        if self.y.size() < delta {
+            self.y = self.y.expand(delta);
+        }
+        if self.z.size() < delta {
+            self.z = self.z.expand(delta);
+        }
+    }
+}
+
+#[test]
+fn aabb_from_points() {
+    let a = Aabb::from_points(Point3::new(1, -1, 2), Point3::new(-1, 1, 3));
+
+    assert_eq!(a.x, Interval::new(-1, 1));
+    assert_eq!(a.y, Interval::new(-1, 1));
+    assert_eq!(a.z, Interval::new(2, 3));
+    assert_eq!(a.longest_axis(), 1);
+}
+
+#[test]
+fn aabb_hit() {
+    use crate::util::vec::Vec3;
+
+    let a = Aabb::from_points(Point3::new(-1, -1, -1), Point3::new(1, 1, 1));
+    let towards = Ray::new(Point3::new(0, 0, -5), Vec3::new(0, 0, 1));
+    let away = Ray::new(Point3::new(0, 0, -5), Vec3::new(0, 0, -1));
+    let beside = Ray::new(Point3::new(2, 0, -5), Vec3::new(0, 0, 1));
+
+    assert!(a.hit(&towards, Interval::new(0, f64::INFINITY)));
+    assert!(!a.hit(&away, Interval::new(0, f64::INFINITY)));
+    assert!(!a.hit(&beside, Interval::new(0, f64::INFINITY)));
+}
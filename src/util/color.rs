@@ -4,12 +4,31 @@ use crate::util::{interval::Interval, vec};
 
 pub type Color = vec::Vec3;
 
+/// Relative luminance of a linear Rec.709 color
 #[inline]
-pub fn linear_to_gamma(linear: f64) -> f64 {
-    if linear > 0.0 {
-        return linear.sqrt();
+pub fn luminance(c: &Color) -> f64 {
+    return 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z();
+}
+
+/// sRGB opto-electronic transfer function, encoding a linear value for display
+#[inline]
+pub fn srgb_oetf(linear: f64) -> f64 {
+    if linear <= 0.0 {
+        return 0.0;
     }
-    return 0.0;
+    if linear <= 0.0031308 {
+        return 12.92 * linear;
+    }
+    return 1.055 * linear.powf(1.0 / 2.4) - 0.055;
+}
+
+/// Inverse of `srgb_oetf`, decoding an sRGB encoded value back to linear
+#[inline]
+pub fn srgb_eotf(encoded: f64) -> f64 {
+    if encoded <= 0.04045 {
+        return encoded / 12.92;
+    }
+    return ((encoded + 0.055) / 1.055).powf(2.4);
 }
 
 pub fn print_color(c: &Color) {
@@ -18,9 +37,9 @@ pub fn print_color(c: &Color) {
     let mut g = c.y();
     let mut b = c.z();
 
-    r = linear_to_gamma(r);
-    g = linear_to_gamma(g);
-    b = linear_to_gamma(b);
+    r = srgb_oetf(r);
+    g = srgb_oetf(g);
+    b = srgb_oetf(b);
 
     let rbyte = (256.0 * intensity.clamp(r)) as i16;
     let gbyte = (256.0 * intensity.clamp(g)) as i16;
@@ -28,3 +47,14 @@ pub fn print_color(c: &Color) {
 
     println!("{} {} {}", rbyte, gbyte, bbyte);
 }
+
+#[test]
+fn srgb_transfer_roundtrip() {
+    assert_eq!(srgb_oetf(0.0), 0.0);
+    assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-12);
+    assert!((srgb_oetf(0.0031308) - 0.04045).abs() < 1e-6);
+    for k in 0..=20 {
+        let v = k as f64 / 20.0;
+        assert!((srgb_eotf(srgb_oetf(v)) - v).abs() < 1e-12);
+    }
+}
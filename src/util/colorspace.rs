@@ -0,0 +1,245 @@
+use std::str::FromStr;
+
+use crate::util::color::{srgb_eotf, srgb_oetf, Color};
+
+/// Row major 3x3 matrix acting on colors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat3 {
+    m: [[f64; 3]; 3],
+}
+
+impl Mat3 {
+    pub fn new(m: [[f64; 3]; 3]) -> Self {
+        Self { m: m }
+    }
+
+    pub fn identity() -> Self {
+        return Mat3::diagonal(Color::new(1, 1, 1));
+    }
+
+    pub fn diagonal(d: Color) -> Self {
+        return Mat3::new([[d.x(), 0.0, 0.0], [0.0, d.y(), 0.0], [0.0, 0.0, d.z()]]);
+    }
+
+    pub fn apply(&self, c: Color) -> Color {
+        let m = &self.m;
+        return Color::new(
+            m[0][0] * c.x() + m[0][1] * c.y() + m[0][2] * c.z(),
+            m[1][0] * c.x() + m[1][1] * c.y() + m[1][2] * c.z(),
+            m[2][0] * c.x() + m[2][1] * c.y() + m[2][2] * c.z(),
+        );
+    }
+
+    pub fn mul(&self, other: &Mat3) -> Mat3 {
+        let mut out = [[0.0; 3]; 3];
+        for (i, row) in out.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..3).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        return Mat3::new(out);
+    }
+
+    pub fn add(&self, other: &Mat3) -> Mat3 {
+        let mut out = self.m;
+        for (i, row) in out.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v += other.m[i][j];
+            }
+        }
+        return Mat3::new(out);
+    }
+
+    pub fn transpose(&self) -> Mat3 {
+        let m = &self.m;
+        return Mat3::new([[m[0][0], m[1][0], m[2][0]], [m[0][1], m[1][1], m[2][1]], [m[0][2], m[1][2], m[2][2]]]);
+    }
+
+    pub fn inverse(&self) -> Mat3 {
+        let m = &self.m;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+        let adj = [
+            [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+            [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+            [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+        ];
+        let det = m[0][0] * adj[0][0] + m[0][1] * adj[1][0] + m[0][2] * adj[2][0];
+        let mut out = adj;
+        for row in out.iter_mut() {
+            for v in row.iter_mut() {
+                *v /= det;
+            }
+        }
+        return Mat3::new(out);
+    }
+}
+
+/// CIE xy chromaticity of a reference white
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WhitePoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl WhitePoint {
+    pub const D65: WhitePoint = WhitePoint { x: 0.3127, y: 0.3290 };
+    /// White of the ACES color spaces, close to but not exactly D60
+    pub const ACES: WhitePoint = WhitePoint { x: 0.32168, y: 0.33767 };
+
+    pub fn xyz(&self) -> Color {
+        return xy_to_xyz(self.x, self.y);
+    }
+}
+
+/// Bradford chromatic adaptation of XYZ colors seen under white `from` to how they
+/// appear under white `to`
+pub fn bradford(from: WhitePoint, to: WhitePoint) -> Mat3 {
+    let cone = Mat3::new([
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ]);
+    let src = cone.apply(from.xyz());
+    let dst = cone.apply(to.xyz());
+    let scale = Mat3::diagonal(Color::new(dst.x() / src.x(), dst.y() / src.y(), dst.z() / src.z()));
+    return cone.inverse().mul(&scale).mul(&cone);
+}
+
+/// Linear RGB color spaces a scene can be authored or output in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    /// Rec.709 primaries with a D65 white, the primaries of sRGB
+    #[default]
+    LinearSrgb,
+    /// ACES AP1 primaries with the ACES white
+    AcesCg,
+    /// DCI-P3 primaries with a D65 white
+    DisplayP3,
+}
+
+impl ColorSpace {
+    /// xy chromaticities of the red, green and blue primaries
+    pub fn primaries(&self) -> [(f64, f64); 3] {
+        return match self {
+            ColorSpace::LinearSrgb => [(0.640, 0.330), (0.300, 0.600), (0.150, 0.060)],
+            ColorSpace::AcesCg => [(0.713, 0.293), (0.165, 0.830), (0.128, 0.044)],
+            ColorSpace::DisplayP3 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060)],
+        };
+    }
+
+    pub fn white(&self) -> WhitePoint {
+        return match self {
+            ColorSpace::AcesCg => WhitePoint::ACES,
+            ColorSpace::LinearSrgb | ColorSpace::DisplayP3 => WhitePoint::D65,
+        };
+    }
+
+    /// Matrix taking linear RGB in this space to CIE XYZ under the space's own white
+    pub fn to_xyz(&self) -> Mat3 {
+        let p = self.primaries().map(|(x, y)| xy_to_xyz(x, y));
+        let columns = Mat3::new([
+            [p[0].x(), p[1].x(), p[2].x()],
+            [p[0].y(), p[1].y(), p[2].y()],
+            [p[0].z(), p[1].z(), p[2].z()],
+        ]);
+        // Scale the primaries so RGB white lands on the white point
+        let s = columns.inverse().apply(self.white().xyz());
+        return columns.mul(&Mat3::diagonal(s));
+    }
+
+    /// Matrix taking linear RGB in this space to `target`, adapting between their
+    /// whites with Bradford so white stays white
+    pub fn conversion_to(&self, target: ColorSpace) -> Mat3 {
+        if *self == target {
+            return Mat3::identity();
+        }
+        let adapt = bradford(self.white(), target.white());
+        return target.to_xyz().inverse().mul(&adapt).mul(&self.to_xyz());
+    }
+
+    pub fn convert(&self, c: Color, target: ColorSpace) -> Color {
+        return self.conversion_to(target).apply(c);
+    }
+}
+
+impl FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s.to_ascii_lowercase().as_str() {
+            "srgb" | "rec709" | "linear-srgb" => Ok(ColorSpace::LinearSrgb),
+            "acescg" => Ok(ColorSpace::AcesCg),
+            "p3" | "display-p3" => Ok(ColorSpace::DisplayP3),
+            _ => Err(format!("unknown color space `{}`", s)),
+        };
+    }
+}
+
+/// How values are encoded in a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transfer {
+    /// Float formats store scene linear values as they are
+    #[default]
+    Linear,
+    /// 8 bit formats store values through the sRGB curve, also used by Display P3
+    Srgb,
+}
+
+impl Transfer {
+    pub fn encode(&self, c: Color) -> Color {
+        return match self {
+            Transfer::Linear => c,
+            Transfer::Srgb => Color::new(srgb_oetf(c.x()), srgb_oetf(c.y()), srgb_oetf(c.z())),
+        };
+    }
+
+    pub fn decode(&self, c: Color) -> Color {
+        return match self {
+            Transfer::Linear => c,
+            Transfer::Srgb => Color::new(srgb_eotf(c.x()), srgb_eotf(c.y()), srgb_eotf(c.z())),
+        };
+    }
+}
+
+fn xy_to_xyz(x: f64, y: f64) -> Color {
+    return Color::new(x / y, 1.0, (1.0 - x - y) / y);
+}
+
+#[cfg(test)]
+fn assert_close(a: Color, b: Color, eps: f64) {
+    assert!((a - b).length() < eps, "{:?} != {:?}", a, b);
+}
+
+#[test]
+fn srgb_to_xyz_matches_standard() {
+    let m = ColorSpace::LinearSrgb.to_xyz();
+    assert_close(m.apply(Color::new(1, 0, 0)), Color::new(0.4124, 0.2126, 0.0193), 1e-3);
+    assert_close(m.apply(Color::new(0, 1, 0)), Color::new(0.3576, 0.7152, 0.1192), 1e-3);
+    assert_close(m.apply(Color::new(1, 1, 1)), WhitePoint::D65.xyz(), 1e-9);
+}
+
+#[test]
+fn conversions_keep_white_and_roundtrip() {
+    let spaces = [ColorSpace::LinearSrgb, ColorSpace::AcesCg, ColorSpace::DisplayP3];
+    let c = Color::new(0.2, 0.5, 0.9);
+    for from in spaces {
+        for to in spaces {
+            assert_close(from.convert(Color::new(1, 1, 1), to), Color::new(1, 1, 1), 1e-9);
+            assert_close(to.convert(from.convert(c, to), from), c, 1e-9);
+        }
+    }
+    // First column of the published Bradford adapted sRGB to ACEScg matrix
+    assert_close(
+        ColorSpace::LinearSrgb.convert(Color::new(1, 0, 0), ColorSpace::AcesCg),
+        Color::new(0.6131, 0.0702, 0.0206),
+        1e-3,
+    );
+}
+
+#[test]
+fn bradford_adapts_whites() {
+    let m = bradford(WhitePoint::D65, WhitePoint::ACES);
+    assert_close(m.apply(WhitePoint::D65.xyz()), WhitePoint::ACES.xyz(), 1e-9);
+    let c = Color::new(0.3, 0.2, 0.1);
+    assert_close(bradford(WhitePoint::D65, WhitePoint::D65).apply(c), c, 1e-12);
+}
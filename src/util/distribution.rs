@@ -0,0 +1,169 @@
+/// Piecewise constant distribution over [0, 1] built from non negative weights,
+/// sampled by inverting its CDF
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+/// Piecewise constant distribution over [0, 1]^2, sampled by first picking a
+/// row from the marginal and then a column from that row's conditional
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution1D {
+    pub fn new(func: &[f64]) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1].max(0.0) / n as f64;
+        }
+
+        let integral = cdf[n];
+        if integral == 0.0 {
+            // Fall back to uniform sampling when every weight is zero
+            for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+                *c = i as f64 / n as f64;
+            }
+        } else {
+            for c in cdf.iter_mut().skip(1) {
+                *c /= integral;
+            }
+        }
+
+        Self {
+            func: func.iter().map(|f| f.max(0.0)).collect(),
+            cdf: cdf,
+            integral: integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        return self.func.len();
+    }
+
+    pub fn integral(&self) -> f64 {
+        return self.integral;
+    }
+
+    /// Maps `u` in [0, 1) to a continuous sample in [0, 1), returning
+    /// the sample, its density and the index of the segment it fell into
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let offset = self.find_segment(u);
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+
+        let pdf = self.pdf_segment(offset);
+        return ((offset as f64 + du) / self.count() as f64, pdf, offset);
+    }
+
+    /// Picks a segment index with probability proportional to its weight
+    pub fn sample_discrete(&self, u: f64) -> (usize, f64) {
+        let offset = self.find_segment(u);
+        return (offset, self.discrete_pdf(offset));
+    }
+
+    pub fn discrete_pdf(&self, index: usize) -> f64 {
+        return self.cdf[index + 1] - self.cdf[index];
+    }
+
+    /// Density of the continuous distribution at `x` in [0, 1]
+    pub fn pdf(&self, x: f64) -> f64 {
+        let n = self.count();
+        let offset = ((x * n as f64) as usize).min(n - 1);
+        return self.pdf_segment(offset);
+    }
+
+    fn pdf_segment(&self, offset: usize) -> f64 {
+        if self.integral == 0.0 {
+            return 1.0;
+        }
+        return self.func[offset] / self.integral;
+    }
+
+    fn find_segment(&self, u: f64) -> usize {
+        // Largest index whose CDF value is <= u, skipping zero width segments
+        let idx = self.cdf.partition_point(|&c| c <= u);
+        return idx.saturating_sub(1).min(self.count() - 1);
+    }
+}
+
+impl Distribution2D {
+    /// Builds the distribution from `nu * nv` weights stored row by row
+    pub fn new(func: &[f64], nu: usize, nv: usize) -> Self {
+        let conditional: Vec<Distribution1D> = (0..nv)
+            .map(|v| Distribution1D::new(&func[v * nu..(v + 1) * nu]))
+            .collect();
+        let marginal_func: Vec<f64> = conditional.iter().map(|c| c.integral()).collect();
+
+        Self {
+            conditional: conditional,
+            marginal: Distribution1D::new(&marginal_func),
+        }
+    }
+
+    /// Maps two uniform numbers to a point in [0, 1)^2 and its density
+    pub fn sample_continuous(&self, u0: f64, u1: f64) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u1);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u0);
+        return ((u, v), pdf_u * pdf_v);
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let nv = self.conditional.len();
+        let row = ((v * nv as f64) as usize).min(nv - 1);
+        return self.marginal.pdf(v) * self.conditional[row].pdf(u);
+    }
+}
+
+#[test]
+fn distribution1d_pdf_integrates_to_one() {
+    let d = Distribution1D::new(&[1.0, 0.0, 3.0, 4.0]);
+    let n = 1000;
+    let integral: f64 = (0..n)
+        .map(|i| d.pdf((i as f64 + 0.5) / n as f64) / n as f64)
+        .sum();
+
+    assert!((integral - 1.0).abs() < 1e-9);
+    assert_eq!(d.integral(), 2.0);
+}
+
+#[test]
+fn distribution1d_sampling_skips_zero_weights() {
+    let d = Distribution1D::new(&[1.0, 0.0, 3.0, 4.0]);
+
+    for i in 0..100 {
+        let (x, pdf, offset) = d.sample_continuous(i as f64 / 100.0);
+        assert_ne!(offset, 1);
+        assert!(pdf > 0.0);
+        assert_eq!(d.pdf(x), pdf);
+    }
+    assert_eq!(d.sample_discrete(0.1), (0, 0.125));
+    assert_eq!(d.sample_discrete(0.99), (3, 0.5));
+}
+
+#[test]
+fn distribution2d_pdf_integrates_to_one() {
+    let func = [0.0, 1.0, 2.0, 5.0, 0.5, 0.0];
+    let d = Distribution2D::new(&func, 3, 2);
+    let n = 300;
+    let mut integral = 0.0;
+    for i in 0..n {
+        for j in 0..n {
+            let u = (i as f64 + 0.5) / n as f64;
+            let v = (j as f64 + 0.5) / n as f64;
+            integral += d.pdf(u, v) / (n * n) as f64;
+        }
+    }
+    assert!((integral - 1.0).abs() < 1e-9);
+
+    let ((u, v), pdf) = d.sample_continuous(0.3, 0.7);
+    assert!((d.pdf(u, v) - pdf).abs() < 1e-12);
+}
@@ -0,0 +1,129 @@
+use std::f64::consts::PI;
+
+/// Pixel reconstruction filter, evaluated at an offset in pixels from the pixel center.
+/// All filters are separable and zero outside `radius` on either axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Box { radius: f64 },
+    Tent { radius: f64 },
+    Gaussian { radius: f64, sigma: f64 },
+    /// Mitchell-Netravali cubic, with negative lobes for most choices of `b` and `c`
+    Mitchell { radius: f64, b: f64, c: f64 },
+    /// Sinc windowed by a sinc stretched over `tau` lobes, also with negative lobes
+    Lanczos { radius: f64, tau: f64 },
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+impl Filter {
+    pub fn box_filter(radius: f64) -> Self {
+        Filter::Box { radius: radius }
+    }
+
+    pub fn tent(radius: f64) -> Self {
+        Filter::Tent { radius: radius }
+    }
+
+    pub fn gaussian(radius: f64, sigma: f64) -> Self {
+        Filter::Gaussian {
+            radius: radius,
+            sigma: sigma,
+        }
+    }
+
+    pub fn mitchell(radius: f64) -> Self {
+        Filter::Mitchell {
+            radius: radius,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        }
+    }
+
+    pub fn lanczos(radius: f64) -> Self {
+        Filter::Lanczos {
+            radius: radius,
+            tau: 3.0,
+        }
+    }
+
+    pub fn radius(&self) -> f64 {
+        return match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius, .. } => radius,
+        };
+    }
+
+    pub fn eval(&self, x: f64, y: f64) -> f64 {
+        let r = self.radius();
+        if x.abs() > r || y.abs() > r {
+            return 0.0;
+        }
+        return self.eval_1d(x) * self.eval_1d(y);
+    }
+
+    fn eval_1d(&self, x: f64) -> f64 {
+        return match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { radius } => (radius - x.abs()).max(0.0),
+            Filter::Gaussian { radius, sigma } => {
+                let g = |v: f64| (-v * v / (2.0 * sigma * sigma)).exp();
+                (g(x) - g(radius)).max(0.0)
+            }
+            Filter::Mitchell { radius, b, c } => mitchell_1d(2.0 * x / radius, b, c),
+            Filter::Lanczos { tau, .. } => sinc(x) * sinc(x / tau),
+        };
+    }
+}
+
+/// Mitchell-Netravali cubic on [-2, 2]
+fn mitchell_1d(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    if x > 2.0 {
+        return 0.0;
+    }
+    let x2 = x * x;
+    let x3 = x2 * x;
+    let v = if x > 1.0 {
+        (-b - 6.0 * c) * x3 + (6.0 * b + 30.0 * c) * x2 + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+    } else {
+        (12.0 - 9.0 * b - 6.0 * c) * x3 + (-18.0 + 12.0 * b + 6.0 * c) * x2 + (6.0 - 2.0 * b)
+    };
+    return v / 6.0;
+}
+
+#[inline]
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    return (PI * x).sin() / (PI * x);
+}
+
+#[test]
+fn filters_vanish_outside_radius() {
+    for f in [
+        Filter::default(),
+        Filter::tent(1.0),
+        Filter::gaussian(1.5, 0.5),
+        Filter::mitchell(2.0),
+        Filter::lanczos(3.0),
+    ] {
+        assert!(f.eval(0.0, 0.0) > 0.0, "{:?}", f);
+        assert_eq!(f.eval(f.radius() + 0.01, 0.0), 0.0, "{:?}", f);
+        assert_eq!(f.eval(0.0, -f.radius() - 0.01), 0.0, "{:?}", f);
+    }
+}
+
+#[test]
+fn filters_with_negative_lobes() {
+    assert!(Filter::mitchell(2.0).eval(1.5, 0.0) < 0.0);
+    assert!(Filter::lanczos(3.0).eval(1.5, 0.0) < 0.0);
+    assert!(Filter::gaussian(1.5, 0.5).eval(1.0, 0.0) > 0.0);
+}
@@ -0,0 +1,534 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::util::{
+    color::{srgb_oetf, Color},
+    colorspace::{ColorSpace, Transfer},
+    interval::Interval,
+    tonemap::DisplayTransform,
+};
+
+/// Floating point RGB image stored row by row from the top left corner
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    data: Vec<Color>,
+}
+
+/// Largest image the readers accept, well beyond any real render but small enough
+/// that a corrupt header can't ask for an absurd allocation
+const MAX_PIXELS: usize = 1 << 28;
+
+fn invalid_data(msg: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+}
+
+/// Reads the raw pixel data of a `width` by `height` image, growing the buffer as the
+/// data arrives rather than trusting the header's size up front
+fn read_pixel_bytes<R: Read>(reader: &mut R, width: usize, height: usize, bytes_per_pixel: usize) -> io::Result<Vec<u8>> {
+    let len = width
+        .checked_mul(height)
+        .filter(|&n| n <= MAX_PIXELS)
+        .and_then(|n| n.checked_mul(bytes_per_pixel))
+        .ok_or_else(|| invalid_data("image dimensions are too large"))?;
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "image data ends early"));
+    }
+    return Ok(bytes);
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width: width,
+            height: height,
+            data: vec![Color::default(); width * height],
+        }
+    }
+
+    pub fn from_data(width: usize, height: usize, data: Vec<Color>) -> Self {
+        assert_eq!(data.len(), width * height, "pixel count does not match image size");
+        Self {
+            width: width,
+            height: height,
+            data: data,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        return self.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.height;
+    }
+
+    pub fn data(&self) -> &[Color] {
+        return &self.data;
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        return self.data[y * self.width + x];
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, c: Color) {
+        self.data[y * self.width + x] = c;
+    }
+
+    /// Loads a Radiance `.hdr`, portable float map `.pfm` or binary `.ppm` depending on
+    /// the file extension. PPM values are decoded from the sRGB curve, so the result is
+    /// always linear.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let ext = path
+            .as_ref()
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let mut reader = BufReader::new(File::open(&path)?);
+
+        let img = match ext.as_deref() {
+            Some("hdr") => Self::read_hdr(&mut reader)?,
+            Some("pfm") => Self::read_pfm(&mut reader)?,
+            Some("ppm") => Self::read_ppm(&mut reader)?.map(|c| Transfer::Srgb.decode(c)),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "only .hdr, .pfm and .ppm images can be loaded",
+                ))
+            }
+        };
+        if img.data.is_empty() {
+            return Err(invalid_data("image has no pixels"));
+        }
+        return Ok(img);
+    }
+
+    /// Loads an image authored with the primaries of `source` and converts it to the
+    /// `working` space the scene is rendered in
+    pub fn load_in<P: AsRef<Path>>(path: P, source: ColorSpace, working: ColorSpace) -> io::Result<Self> {
+        let m = source.conversion_to(working);
+        return Ok(Self::load(path)?.map(|c| m.apply(c)));
+    }
+
+    /// Saves a linear image rendered in `working` with the primaries of `target`,
+    /// encoded the way the format expects, see `save`
+    pub fn save_in<P: AsRef<Path>>(&self, path: P, working: ColorSpace, target: ColorSpace) -> io::Result<()> {
+        let m = working.conversion_to(target);
+        return self.map(|c| m.apply(c)).save(path);
+    }
+
+    pub fn map(&self, f: impl Fn(Color) -> Color) -> Self {
+        return Self::from_data(self.width, self.height, self.data.iter().map(|&c| f(c)).collect());
+    }
+
+    /// Saves as Radiance `.hdr`, portable float map `.pfm` or gamma encoded binary `.ppm`
+    /// depending on the file extension
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let ext = path
+            .as_ref()
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let format = match ext.as_deref() {
+            Some(e @ ("hdr" | "pfm" | "ppm")) => e.to_string(),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "only .hdr, .pfm and .ppm images can be saved",
+                ))
+            }
+        };
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        return match format.as_str() {
+            "hdr" => self.write_hdr(&mut writer),
+            "pfm" => self.write_pfm(&mut writer),
+            _ => self.write_ppm(&mut writer),
+        };
+    }
+
+    pub fn save_pfm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        return self.write_pfm(&mut writer);
+    }
+
+    pub fn read_pfm<R: BufRead>(reader: &mut R) -> io::Result<Self> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let channels = match line.trim() {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err(invalid_data("missing PFM magic")),
+        };
+
+        line.clear();
+        reader.read_line(&mut line)?;
+        let dims: Vec<usize> = line
+            .split_whitespace()
+            .map(|f| f.parse::<usize>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid_data("invalid PFM dimensions"))?;
+        if dims.len() != 2 {
+            return Err(invalid_data("invalid PFM dimensions"));
+        }
+        let (width, height) = (dims[0], dims[1]);
+
+        line.clear();
+        reader.read_line(&mut line)?;
+        let scale: f64 = line
+            .trim()
+            .parse()
+            .map_err(|_| invalid_data("invalid PFM scale"))?;
+        let little_endian = scale < 0.0;
+
+        let bytes = read_pixel_bytes(reader, width, height, channels * 4)?;
+        let values: Vec<f64> = bytes
+            .chunks_exact(4)
+            .map(|b| {
+                let b = [b[0], b[1], b[2], b[3]];
+                let v = if little_endian {
+                    f32::from_le_bytes(b)
+                } else {
+                    f32::from_be_bytes(b)
+                };
+                v as f64
+            })
+            .collect();
+
+        // PFM scanlines are stored bottom to top
+        let mut img = Self::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let i = ((height - 1 - y) * width + x) * channels;
+                let c = if channels == 3 {
+                    Color::new(values[i], values[i + 1], values[i + 2])
+                } else {
+                    Color::from(values[i])
+                };
+                img.set(x, y, c);
+            }
+        }
+        return Ok(img);
+    }
+
+    pub fn write_pfm<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                for v in self.get(x, y).v() {
+                    writer.write_all(&(v as f32).to_le_bytes())?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Display referred copy of the image, see `DisplayTransform`
+    pub fn to_display(&self, transform: &DisplayTransform) -> Self {
+        return self.map(|c| transform.apply(c));
+    }
+
+    /// Reads a binary PPM with values scaled to [0, 1] but still sRGB encoded
+    pub fn read_ppm<R: BufRead>(reader: &mut R) -> io::Result<Self> {
+        let mut fields = Vec::new();
+        while fields.len() < 4 {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid_data("unexpected end of PPM header"));
+            }
+            let content = line.split('#').next().unwrap_or("");
+            fields.extend(content.split_whitespace().map(|f| f.to_string()));
+        }
+        if fields.len() != 4 || fields[0] != "P6" {
+            return Err(invalid_data("only binary P6 PPM images with a newline after the header are supported"));
+        }
+        let dims: Vec<usize> = fields[1..]
+            .iter()
+            .map(|f| f.parse::<usize>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid_data("invalid PPM header"))?;
+        let (width, height, max) = (dims[0], dims[1], dims[2]);
+        if max == 0 || max > 65535 {
+            return Err(invalid_data("invalid PPM maximum value"));
+        }
+
+        let bytes_per_value = if max < 256 { 1 } else { 2 };
+        let bytes = read_pixel_bytes(reader, width, height, 3 * bytes_per_value)?;
+        let values: Vec<f64> = bytes
+            .chunks_exact(bytes_per_value)
+            .map(|b| {
+                let v = if bytes_per_value == 1 {
+                    b[0] as f64
+                } else {
+                    u16::from_be_bytes([b[0], b[1]]) as f64
+                };
+                v / max as f64
+            })
+            .collect();
+        let data = values.chunks_exact(3).map(|v| Color::new(v[0], v[1], v[2])).collect();
+        return Ok(Self::from_data(width, height, data));
+    }
+
+    /// Writes an 8 bit binary PPM, sRGB encoded the same way as `print_color`. Values
+    /// are clipped, so tone map HDR images with `to_display` first.
+    pub fn write_ppm<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        let intensity = Interval::new(0.000, 0.999);
+        for c in &self.data {
+            for v in c.v() {
+                writer.write_all(&[(256.0 * intensity.clamp(srgb_oetf(v))) as u8])?;
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn read_hdr<R: BufRead>(reader: &mut R) -> io::Result<Self> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("#?") {
+            return Err(invalid_data("missing Radiance HDR magic"));
+        }
+
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid_data("unexpected end of HDR header"));
+            }
+            let l = line.trim();
+            if l.is_empty() {
+                break;
+            }
+            if l.starts_with("FORMAT=") && l != "FORMAT=32-bit_rle_rgbe" {
+                return Err(invalid_data("only RGBE HDR images are supported"));
+            }
+        }
+
+        line.clear();
+        reader.read_line(&mut line)?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 4 || fields[0] != "-Y" || fields[2] != "+X" {
+            return Err(invalid_data("only -Y H +X W oriented HDR images are supported"));
+        }
+        let height: usize = fields[1].parse().map_err(|_| invalid_data("invalid HDR height"))?;
+        let width: usize = fields[3].parse().map_err(|_| invalid_data("invalid HDR width"))?;
+        if width == 0 || height == 0 {
+            return Err(invalid_data("image has no pixels"));
+        }
+        if width.checked_mul(height).is_none_or(|n| n > MAX_PIXELS) {
+            return Err(invalid_data("image dimensions are too large"));
+        }
+
+        // Like the other readers, the pixels grow with the data rather than being
+        // allocated for the header's size up front
+        let mut data = Vec::new();
+        for _ in 0..height {
+            Self::read_hdr_scanline(reader, width, &mut data)?;
+        }
+        return Ok(Self::from_data(width, height, data));
+    }
+
+    /// Appends the `width` pixels of the next scanline to `data`
+    fn read_hdr_scanline<R: Read>(reader: &mut R, width: usize, data: &mut Vec<Color>) -> io::Result<()> {
+        let mut first = [0u8; 4];
+        reader.read_exact(&mut first)?;
+
+        let is_rle = (8..0x8000).contains(&width)
+            && first[0] == 2
+            && first[1] == 2
+            && (((first[2] as usize) << 8) | first[3] as usize) == width;
+
+        if !is_rle {
+            data.push(rgbe_to_color(first));
+            let mut px = [0u8; 4];
+            for _ in 1..width {
+                reader.read_exact(&mut px)?;
+                data.push(rgbe_to_color(px));
+            }
+            return Ok(());
+        }
+
+        // New style RLE stores each of the four components as its own run encoded
+        // channel. Such scanlines are under 0x8000 pixels, so they are buffered whole.
+        let mut scanline = vec![[0u8; 4]; width];
+        for c in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let mut count = [0u8; 1];
+                reader.read_exact(&mut count)?;
+                let mut n = count[0] as usize;
+                if n > 128 {
+                    n -= 128;
+                    if x + n > width {
+                        return Err(invalid_data("HDR run overflows scanline"));
+                    }
+                    let mut value = [0u8; 1];
+                    reader.read_exact(&mut value)?;
+                    for px in &mut scanline[x..x + n] {
+                        px[c] = value[0];
+                    }
+                } else {
+                    if n == 0 || x + n > width {
+                        return Err(invalid_data("invalid HDR run length"));
+                    }
+                    let mut values = vec![0u8; n];
+                    reader.read_exact(&mut values)?;
+                    for (px, v) in scanline[x..x + n].iter_mut().zip(values) {
+                        px[c] = v;
+                    }
+                }
+                x += n;
+            }
+        }
+        data.extend(scanline.iter().map(|rgbe| rgbe_to_color(*rgbe)));
+        return Ok(());
+    }
+
+    /// Writes an uncompressed Radiance RGBE image
+    pub fn write_hdr<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(
+            writer,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            self.height, self.width
+        )?;
+        for c in &self.data {
+            writer.write_all(&color_to_rgbe(*c))?;
+        }
+        return Ok(());
+    }
+}
+
+fn rgbe_to_color(rgbe: [u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::default();
+    }
+    let f = 2.0_f64.powi(rgbe[3] as i32 - (128 + 8));
+    return Color::new(
+        (rgbe[0] as f64 + 0.5) * f,
+        (rgbe[1] as f64 + 0.5) * f,
+        (rgbe[2] as f64 + 0.5) * f,
+    );
+}
+
+fn color_to_rgbe(c: Color) -> [u8; 4] {
+    let v = c.x().max(c.y()).max(c.z());
+    if v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // v = mantissa * 2^exponent with mantissa in [0.5, 1)
+    let exponent = v.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2.0_f64.powi(exponent);
+    return [
+        (c.x().max(0.0) * scale) as u8,
+        (c.y().max(0.0) * scale) as u8,
+        (c.z().max(0.0) * scale) as u8,
+        (exponent + 128) as u8,
+    ];
+}
+
+#[test]
+fn image_pfm_roundtrip() {
+    let mut img = Image::new(3, 2);
+    img.set(0, 0, Color::new(1.5, 0.25, 0.0));
+    img.set(2, 1, Color::new(0.0, 8.0, 0.125));
+
+    let mut bytes = Vec::new();
+    img.write_pfm(&mut bytes).unwrap();
+
+    assert_eq!(Image::read_pfm(&mut bytes.as_slice()).unwrap(), img);
+}
+
+#[test]
+fn image_hdr_roundtrip() {
+    let mut img = Image::new(2, 2);
+    img.set(0, 0, Color::new(1.0, 0.5, 0.25));
+    img.set(1, 1, Color::new(100.0, 3.0, 0.0));
+
+    let mut bytes = Vec::new();
+    img.write_hdr(&mut bytes).unwrap();
+    let read = Image::read_hdr(&mut bytes.as_slice()).unwrap();
+
+    for (a, b) in read.data().iter().zip(img.data()) {
+        let tolerance = 0.01 * b.x().max(b.y()).max(b.z()).max(1e-3);
+        assert!((*a - *b).length() < tolerance, "{} != {}", a, b);
+    }
+}
+
+#[test]
+fn image_hdr_rle_scanline() {
+    // Eight pixels, every channel written as a single run of a repeated value
+    let mut bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+    bytes.extend([2, 2, 0, 8]);
+    for v in [128u8, 64, 32, 129] {
+        bytes.extend([128 + 8, v]);
+    }
+    let img = Image::read_hdr(&mut bytes.as_slice()).unwrap();
+
+    assert_eq!(img.width(), 8);
+    assert_eq!(img.get(7, 0), rgbe_to_color([128, 64, 32, 129]));
+}
+
+#[test]
+fn empty_images_are_rejected() {
+    let bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 0\n";
+    assert_eq!(Image::read_hdr(&mut &bytes[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    let bytes = b"#?RADIANCE\n\n-Y 100000 +X 100000\n";
+    assert_eq!(Image::read_hdr(&mut &bytes[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    // Within the size limit, but the missing pixels are noticed before any are stored
+    let bytes = b"#?RADIANCE\n\n-Y 16384 +X 16384\n\x01\x02\x03\x80";
+    assert_eq!(Image::read_hdr(&mut &bytes[..]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    let bytes = b"PF\n100000 100000\n-1.0\n";
+    assert_eq!(Image::read_pfm(&mut &bytes[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    let path = std::env::temp_dir().join(format!("raytracer-empty-{}.pfm", std::process::id()));
+    Image::new(0, 0).save(&path).unwrap();
+    let err = Image::load(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn image_ppm_srgb_encoded() {
+    let img = Image::from_data(2, 1, vec![Color::new(0.25, 0, 4), Color::new(1, 1, 1)]);
+    let mut bytes = Vec::new();
+    img.write_ppm(&mut bytes).unwrap();
+
+    let header = b"P6\n2 1\n255\n";
+    assert_eq!(&bytes[..header.len()], header);
+    assert_eq!(&bytes[header.len()..], &[137, 0, 255, 255, 255, 255]);
+
+    let display = img.to_display(&DisplayTransform::new(-2.0, Default::default()));
+    assert_eq!(display.get(0, 0), Color::new(0.0625, 0, 1));
+}
+
+#[test]
+fn ppm_headers_are_checked() {
+    let huge = b"P6\n18446744073709551615 18446744073709551615\n255\n";
+    assert_eq!(Image::read_ppm(&mut &huge[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    let large = b"P6\n100000 100000\n255\n";
+    assert_eq!(Image::read_ppm(&mut &large[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    let truncated = b"P6\n2 2\n255\n\x00\x01\x02";
+    assert_eq!(Image::read_ppm(&mut &truncated[..]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn image_ppm_roundtrip_decodes_srgb() {
+    let img = Image::from_data(2, 1, vec![Color::new(0.0, 0.5, 1.0), Color::new(0.2, 0.04, 0.8)]);
+    let mut bytes = Vec::new();
+    img.write_ppm(&mut bytes).unwrap();
+
+    let encoded = Image::read_ppm(&mut bytes.as_slice()).unwrap();
+    let decoded = encoded.map(|c| Transfer::Srgb.decode(c));
+    for (a, b) in decoded.data().iter().zip(img.data()) {
+        assert!((*a - *b).length() < 0.01, "{:?} != {:?}", a, b);
+    }
+
+    let with_comment = b"P6\n# made by hand\n1 1\n255\n\xff\x00\x80";
+    let px = Image::read_ppm(&mut &with_comment[..]).unwrap().get(0, 0);
+    assert_eq!(px, Color::new(1.0, 0.0, 128.0 / 255.0));
+}
@@ -2,7 +2,7 @@ use core::f64;
 
 use num::ToPrimitive;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Interval {
     pub min: f64,
     pub max: f64,
@@ -29,6 +29,13 @@ impl Interval {
         }
     }
 
+    pub fn enclosing(a: &Interval, b: &Interval) -> Self {
+        Self {
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+        }
+    }
+
     pub fn size(&self) -> f64 {
         return self.max - self.min;
     }
@@ -61,6 +68,11 @@ impl Interval {
         };
     }
 
+    pub fn expand(&self, delta: f64) -> Self {
+        let padding = delta / 2.0;
+        return Self::new(self.min - padding, self.max + padding);
+    }
+
     pub const fn empty() -> Self {
         Self {
             min: f64::INFINITY,
@@ -104,3 +116,19 @@ fn interval_clamp() {
     assert_eq!(a.clamp(-23), -10.0);
     assert_eq!(a.clamp(-5.2), -5.2);
 }
+
+#[test]
+fn interval_enclosing() {
+    let a = Interval::new(-1, 2);
+    let b = Interval::new(0.5, 4);
+
+    assert_eq!(Interval::enclosing(&a, &b), Interval::new(-1, 4));
+    assert_eq!(Interval::enclosing(&Interval::empty(), &b), b);
+}
+
+#[test]
+fn interval_expand() {
+    let a = Interval::new(1, 2).expand(1.0);
+
+    assert_eq!(a, Interval::new(0.5, 2.5));
+}
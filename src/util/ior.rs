@@ -0,0 +1,89 @@
+/// Wavelength of the sodium d line in nm, where catalogue refractive indices are quoted
+pub const D_LINE: f64 = 587.56;
+
+/// Refractive index as a function of wavelength
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ior {
+    /// Same index at every wavelength, no dispersion
+    Constant(f64),
+    /// `n(λ) = a + b / λ²` with `λ` in micrometres
+    Cauchy { a: f64, b: f64 },
+    /// `n²(λ) = 1 + Σ b_i λ² / (λ² - c_i)` with `λ` in micrometres and `c_i` in µm²
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Ior {
+    /// Schott N-BK7 borosilicate crown glass
+    pub const BK7: Ior = Ior::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+    /// Fused silica after Malitson (1965)
+    pub const FUSED_SILICA: Ior = Ior::Sellmeier {
+        b: [0.6961663, 0.4079426, 0.8974794],
+        c: [0.00467914826, 0.0135120631, 97.9340025],
+    };
+    /// Diamond after Peter (1923)
+    pub const DIAMOND: Ior = Ior::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030625, 0.011236, 0.0],
+    };
+
+    /// Index at wavelength `lambda` in nm
+    pub fn at(&self, lambda: f64) -> f64 {
+        let um = lambda * 1e-3;
+        let um2 = um * um;
+        return match *self {
+            Ior::Constant(n) => n,
+            Ior::Cauchy { a, b } => a + b / um2,
+            Ior::Sellmeier { b, c } => {
+                let n2 = 1.0 + (0..3).map(|i| b[i] * um2 / (um2 - c[i])).sum::<f64>();
+                n2.max(1.0).sqrt()
+            }
+        };
+    }
+
+    /// Whether the index changes with wavelength
+    pub fn is_dispersive(&self) -> bool {
+        return match *self {
+            Ior::Constant(_) => false,
+            Ior::Cauchy { b, .. } => b != 0.0,
+            Ior::Sellmeier { .. } => true,
+        };
+    }
+
+    /// Abbe number `(n_d - 1) / (n_F - n_C)`, lower means stronger dispersion
+    pub fn abbe_number(&self) -> f64 {
+        let spread = self.at(486.13) - self.at(656.27);
+        if spread == 0.0 {
+            return f64::INFINITY;
+        }
+        return (self.at(D_LINE) - 1.0) / spread;
+    }
+}
+
+impl Default for Ior {
+    fn default() -> Self {
+        Ior::Constant(1.5)
+    }
+}
+
+#[test]
+fn presets_match_catalogue_values() {
+    assert!((Ior::BK7.at(D_LINE) - 1.5168).abs() < 1e-4);
+    assert!((Ior::BK7.abbe_number() - 64.17).abs() < 0.1);
+    assert!((Ior::FUSED_SILICA.at(D_LINE) - 1.4585).abs() < 1e-4);
+    assert!((Ior::DIAMOND.at(D_LINE) - 2.417).abs() < 2e-3);
+    // Diamond's fire comes from dispersing more than glass
+    assert!(Ior::DIAMOND.abbe_number() < Ior::BK7.abbe_number());
+}
+
+#[test]
+fn blue_bends_more_than_red() {
+    for ior in [Ior::BK7, Ior::FUSED_SILICA, Ior::DIAMOND, Ior::Cauchy { a: 1.5, b: 0.0042 }] {
+        assert!(ior.is_dispersive());
+        assert!(ior.at(450.0) > ior.at(550.0) && ior.at(550.0) > ior.at(650.0), "{:?}", ior);
+    }
+    assert!(!Ior::Constant(1.33).is_dispersive());
+    assert_eq!(Ior::Constant(1.33).at(400.0), 1.33);
+}
@@ -0,0 +1,98 @@
+use std::f64::consts::PI;
+
+use crate::util::{onb::Onb, random_f64, vec::Vec3};
+
+/// GGX (Trowbridge-Reitz) distribution of microfacet normals at an angle with cosine
+/// `cos_h` to the surface normal, for roughness `alpha`
+pub fn ggx_d(cos_h: f64, alpha: f64) -> f64 {
+    if cos_h <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let t = cos_h * cos_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * t * t);
+}
+
+/// Smith masking of the GGX distribution seen from a direction with cosine `cos_v`
+pub fn ggx_g1(cos_v: f64, alpha: f64) -> f64 {
+    let cos2 = (cos_v * cos_v).max(1e-12);
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    return 2.0 / (1.0 + (1.0 + alpha * alpha * tan2).sqrt());
+}
+
+/// Separable Smith shadowing and masking for a pair of directions
+pub fn ggx_g(cos_o: f64, cos_i: f64, alpha: f64) -> f64 {
+    return ggx_g1(cos_o, alpha) * ggx_g1(cos_i, alpha);
+}
+
+/// Samples a microfacet normal around `n` with density `ggx_d(cos_h) * cos_h`
+pub fn sample_ggx(n: Vec3, alpha: f64) -> Vec3 {
+    let u = random_f64();
+    let cos2 = (1.0 - u) / (1.0 + (alpha * alpha - 1.0) * u);
+    return around(n, cos2.sqrt());
+}
+
+/// Berry's distribution (GTR with exponent 1), the long tailed lobe of clear coats
+pub fn gtr1_d(cos_h: f64, alpha: f64) -> f64 {
+    if cos_h <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    return (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_h * cos_h));
+}
+
+/// Samples a microfacet normal around `n` with density `gtr1_d(cos_h) * cos_h`
+pub fn sample_gtr1(n: Vec3, alpha: f64) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos2 = (1.0 - a2.powf(1.0 - random_f64())) / (1.0 - a2);
+    return around(n, cos2.max(0.0).sqrt());
+}
+
+/// `(1 - cos)^5`, the angular falloff of Schlick's Fresnel approximation
+pub fn schlick_weight(cos: f64) -> f64 {
+    let m = (1.0 - cos).clamp(0.0, 1.0);
+    return m * m * m * m * m;
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric boundary for light arriving at an
+/// angle with cosine `cos_i`, `eta` being the index of the far side over the index of
+/// the near side. One under total internal reflection.
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    return 0.5 * (rs * rs + rp * rp);
+}
+
+fn around(n: Vec3, cos_theta: f64) -> Vec3 {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * random_f64();
+    return Onb::new(n).transform(Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+}
+
+#[test]
+fn distributions_are_normalized() {
+    // Projected areas of the microfacets add up to the macro surface
+    for (d, alpha) in [(ggx_d as fn(f64, f64) -> f64, 0.3), (ggx_d, 0.8), (gtr1_d, 0.1), (gtr1_d, 0.5)] {
+        let n = 20000;
+        let mut sum = 0.0;
+        for k in 0..n {
+            let cos = (k as f64 + 0.5) / n as f64;
+            sum += d(cos, alpha) * cos * 2.0 * PI / n as f64;
+        }
+        assert!((sum - 1.0).abs() < 1e-3, "{}", sum);
+    }
+}
+
+#[test]
+fn dielectric_fresnel_limits() {
+    assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-12);
+    assert_eq!(fresnel_dielectric(0.0, 1.5), 1.0);
+    assert_eq!(fresnel_dielectric(0.2, 1.0 / 1.5), 1.0);
+    assert_eq!(schlick_weight(1.0), 0.0);
+}
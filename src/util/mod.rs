@@ -1,10 +1,50 @@
-use rand::random;
+use std::cell::RefCell;
 
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+pub mod aabb;
 pub mod color;
+pub mod colorspace;
+pub mod distribution;
+pub mod filter;
+pub mod image;
 pub mod interval;
+pub mod ior;
+pub mod microfacet;
+pub mod onb;
+pub mod perlin;
+pub mod phase;
 pub mod ray;
+pub mod spectrum;
+pub mod thinfilm;
+pub mod tonemap;
 pub mod vec;
+pub mod voxel;
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Uniform random number in [0, 1) from this thread's generator, which the camera
+/// reseeds per pixel and pass so renders are reproducible
+pub fn random_f64() -> f64 {
+    return RNG.with(|rng| rng.borrow_mut().gen::<f64>());
+}
+
+pub fn seed_random(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
 
 pub fn random_range(min: f64, max: f64) -> f64 {
-    return min + (max - min) * random::<f64>();
+    return min + (max - min) * random_f64();
+}
+
+#[test]
+fn seeded_random_repeats() {
+    seed_random(7);
+    let a: Vec<f64> = (0..4).map(|_| random_f64()).collect();
+    seed_random(7);
+    let b: Vec<f64> = (0..4).map(|_| random_f64()).collect();
+    assert_eq!(a, b);
+    assert!(a.iter().all(|&x| (0.0..1.0).contains(&x)));
 }
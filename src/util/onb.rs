@@ -0,0 +1,53 @@
+use crate::util::vec::{cross, Vec3};
+
+/// Orthonormal basis built around a single direction, used to move
+/// locally sampled directions into world space
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Onb {
+    axis: [Vec3; 3],
+}
+
+impl Onb {
+    pub fn new(n: Vec3) -> Self {
+        let w = n.to_normal();
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0, 1, 0)
+        } else {
+            Vec3::new(1, 0, 0)
+        };
+        let v = cross(w, a).to_normal();
+        let u = cross(w, v);
+
+        Self { axis: [u, v, w] }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        return self.axis[0];
+    }
+
+    pub fn v(&self) -> Vec3 {
+        return self.axis[1];
+    }
+
+    pub fn w(&self) -> Vec3 {
+        return self.axis[2];
+    }
+
+    pub fn transform(&self, local: Vec3) -> Vec3 {
+        return local.x() * self.axis[0] + local.y() * self.axis[1] + local.z() * self.axis[2];
+    }
+}
+
+#[test]
+fn onb_is_orthonormal() {
+    use crate::util::vec::dot;
+
+    let b = Onb::new(Vec3::new(0.3, -2.0, 0.5));
+
+    assert!((b.u().length() - 1.0).abs() < 1e-12);
+    assert!((b.v().length() - 1.0).abs() < 1e-12);
+    assert!(dot(b.u(), b.v()).abs() < 1e-12);
+    assert!(dot(b.u(), b.w()).abs() < 1e-12);
+    assert!(dot(b.v(), b.w()).abs() < 1e-12);
+    assert!((b.transform(Vec3::new(0, 0, 1)) - b.w()).near_zero());
+}
@@ -0,0 +1,90 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::util::vec::{dot, Point3, Vec3};
+
+const POINT_COUNT: usize = 256;
+
+/// Perlin gradient noise. The lattice is built from a seed rather than the render's
+/// random numbers, so the pattern doesn't depend on when the scene was built.
+#[derive(Debug, Clone)]
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm: [Vec<usize>; 3],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| loop {
+                let v = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+                let len2 = v.length_squared();
+                if len2 > 1e-6 && len2 <= 1.0 {
+                    break v / len2.sqrt();
+                }
+            })
+            .collect();
+        let mut permutation = || {
+            let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+            p.shuffle(&mut rng);
+            p
+        };
+        let perm = [permutation(), permutation(), permutation()];
+        Self {
+            gradients: gradients,
+            perm: perm,
+        }
+    }
+
+    /// Smooth noise in about [-1, 1] with features one unit apart
+    pub fn noise(&self, p: Point3) -> f64 {
+        let cell = [p.x().floor(), p.y().floor(), p.z().floor()];
+        let f = [p.x() - cell[0], p.y() - cell[1], p.z() - cell[2]];
+        let i = cell.map(|c| c as i64);
+        // Hermite smoothing of the interpolation weights
+        let w = f.map(|t| t * t * (3.0 - 2.0 * t));
+
+        let mut sum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let idx = self.perm[0][((i[0] + di) & 255) as usize]
+                        ^ self.perm[1][((i[1] + dj) & 255) as usize]
+                        ^ self.perm[2][((i[2] + dk) & 255) as usize];
+                    let offset = Vec3::new(f[0] - di as f64, f[1] - dj as f64, f[2] - dk as f64);
+                    let weight = (if di == 1 { w[0] } else { 1.0 - w[0] })
+                        * (if dj == 1 { w[1] } else { 1.0 - w[1] })
+                        * (if dk == 1 { w[2] } else { 1.0 - w[2] });
+                    sum += weight * dot(self.gradients[idx], offset);
+                }
+            }
+        }
+        return sum;
+    }
+
+    /// Sum of `depth` octaves of noise, each at twice the frequency and half the weight
+    pub fn turbulence(&self, p: Point3, depth: usize) -> f64 {
+        let mut sum = 0.0;
+        let mut weight = 1.0;
+        let mut q = p;
+        for _ in 0..depth {
+            sum += weight * self.noise(q);
+            weight *= 0.5;
+            q = 2.0 * q;
+        }
+        return sum.abs();
+    }
+}
+
+#[test]
+fn noise_is_smooth_and_repeatable() {
+    let a = Perlin::new(1);
+    let b = Perlin::new(1);
+    let p = Point3::new(1.3, -2.7, 0.4);
+    assert_eq!(a.noise(p), b.noise(p));
+    // Zero on the lattice, continuous in between
+    assert_eq!(a.noise(Point3::new(3, -1, 2)), 0.0);
+    assert!((a.noise(p) - a.noise(p + Vec3::new(1e-6, 0, 0))).abs() < 1e-4);
+    let spread = (0..100).map(|k| a.noise(Point3::new(0.37 * k as f64, 0.5, 0.5)).abs()).fold(0.0, f64::max);
+    assert!(spread > 0.1 && spread <= 1.5);
+}
@@ -0,0 +1,63 @@
+use std::f64::consts::PI;
+
+use crate::util::{onb::Onb, random_f64, vec::Vec3};
+
+/// Henyey-Greenstein phase function for the cosine between the direction the
+/// light was travelling and the direction it scatters into. `g` > 0 favours
+/// forward scattering, `g` < 0 back scattering and 0 is isotropic.
+pub fn henyey_greenstein(cos_theta: f64, g: f64) -> f64 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    return (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt());
+}
+
+/// Samples a scattered direction for light travelling along `dir`,
+/// distributed according to `henyey_greenstein`
+pub fn sample_henyey_greenstein(dir: Vec3, g: f64) -> Vec3 {
+    let xi = random_f64();
+    let cos_theta = if g.abs() < 1e-3 {
+        1.0 - 2.0 * xi
+    } else {
+        let sqr = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+        (1.0 + g * g - sqr * sqr) / (2.0 * g)
+    }
+    .clamp(-1.0, 1.0);
+
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * random_f64();
+
+    let basis = Onb::new(dir);
+    return basis.transform(Vec3::new(
+        sin_theta * phi.cos(),
+        sin_theta * phi.sin(),
+        cos_theta,
+    ));
+}
+
+#[test]
+fn henyey_greenstein_is_normalized() {
+    for g in [-0.7, 0.0, 0.3, 0.9] {
+        let n = 20000;
+        let mut integral = 0.0;
+        for i in 0..n {
+            let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / n as f64;
+            integral += henyey_greenstein(cos_theta, g) * 2.0 / n as f64;
+        }
+        assert!((2.0 * PI * integral - 1.0).abs() < 1e-3, "g = {}", g);
+    }
+}
+
+#[test]
+fn henyey_greenstein_sample_mean_cosine() {
+    use crate::util::vec::dot;
+
+    let dir = Vec3::new(0, 0, 1);
+    let g = 0.6;
+    let n = 20000;
+    let mut mean = 0.0;
+    for _ in 0..n {
+        mean += dot(sample_henyey_greenstein(dir, g), dir);
+    }
+    mean /= n as f64;
+
+    assert!((mean - g).abs() < 0.03);
+}
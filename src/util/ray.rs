@@ -4,10 +4,16 @@ use num::{FromPrimitive, ToPrimitive};
 
 use crate::util::vec::{Point3, Vec3};
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Ray {
     origin: Point3,
     dir: Vec3,
+    tm: f64,
+    /// Wavelength in nm the ray is restricted to, once something dispersive split it
+    wavelength: Option<f64>,
+    /// RGB channel standing for `wavelength` when rendering in RGB, which spectral
+    /// renders leave unset
+    channel: Option<usize>,
 }
 
 impl Default for Ray {
@@ -15,18 +21,38 @@ impl Default for Ray {
         Self {
             origin: Default::default(),
             dir: Default::default(),
+            tm: Default::default(),
+            wavelength: None,
+            channel: None,
         }
     }
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Self {
+        Self::new_timed(origin, direction, 0.0)
+    }
+
+    pub fn new_timed(origin: Point3, direction: Vec3, time: f64) -> Self {
         Self {
             origin: origin,
             dir: direction,
+            tm: time,
+            wavelength: None,
+            channel: None,
         }
     }
 
+    pub fn with_wavelength(mut self, lambda: Option<f64>) -> Self {
+        self.wavelength = lambda;
+        return self;
+    }
+
+    pub fn with_channel(mut self, channel: Option<usize>) -> Self {
+        self.channel = channel;
+        return self;
+    }
+
     pub fn origin(&self) -> Point3 {
         return self.origin;
     }
@@ -35,6 +61,18 @@ impl Ray {
         return self.dir;
     }
 
+    pub fn time(&self) -> f64 {
+        return self.tm;
+    }
+
+    pub fn wavelength(&self) -> Option<f64> {
+        return self.wavelength;
+    }
+
+    pub fn channel(&self) -> Option<usize> {
+        return self.channel;
+    }
+
     pub fn at<T>(&self, t: T) -> Point3
     where
         T: ToPrimitive+FromPrimitive,
@@ -58,3 +96,13 @@ fn get_ray_at() {
     let a = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(10.0, 5.0, 2.0));
     assert_eq!(a.at(0.5), Point3::new(5.0, 2.5, 1.0))
 }
+
+#[test]
+fn ray_keeps_time() {
+    let a = Ray::new_timed(Point3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 0.25);
+    assert_eq!(a.time(), 0.25);
+    assert_eq!(Ray::new(a.origin(), a.direction()).time(), 0.0);
+    assert_eq!(a.with_wavelength(Some(550.0)).wavelength(), Some(550.0));
+    assert_eq!(a.wavelength(), None);
+    assert_eq!(a.with_channel(Some(2)).channel(), Some(2));
+}
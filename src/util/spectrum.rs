@@ -0,0 +1,301 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use crate::util::{
+    color::Color,
+    colorspace::{ColorSpace, Mat3},
+};
+
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+
+/// Wavelengths standing in for the red, green and blue channels when an RGB render
+/// has to follow a single wavelength, near the peaks of the sRGB primaries
+pub const RGB_WAVELENGTHS: [f64; 3] = [610.0, 550.0, 465.0];
+
+/// CIE standard illuminant D65 from 380 to 780 nm in 10 nm steps
+const D65: [f64; 41] = [
+    49.98, 54.65, 82.75, 91.49, 93.43, 86.68, 104.86, 117.01, 117.81, 114.86, 115.92, 108.81, 109.35, 107.80,
+    104.79, 107.69, 104.41, 104.05, 100.00, 96.33, 95.79, 88.69, 90.01, 89.60, 87.70, 83.29, 83.70, 80.03, 80.21,
+    82.28, 78.28, 69.72, 71.61, 74.35, 61.60, 69.89, 75.09, 63.59, 46.42, 66.81, 63.38,
+];
+
+/// Step of the quadrature used to fit and normalize spectra
+const FIT_STEP: f64 = 5.0;
+
+pub fn d65(lambda: f64) -> f64 {
+    let x = ((lambda - LAMBDA_MIN) / 10.0).clamp(0.0, (D65.len() - 1) as f64);
+    let i = (x as usize).min(D65.len() - 2);
+    let t = x - i as f64;
+    return D65[i] * (1.0 - t) + D65[i + 1] * t;
+}
+
+/// CIE 1931 2° color matching functions from the multi-lobe Gaussian fit of
+/// Wyman, Sloan and Shirley (2013)
+pub fn cie_xyz(lambda: f64) -> Color {
+    let g = |mu: f64, s1: f64, s2: f64| {
+        let t = (lambda - mu) / if lambda < mu { s1 } else { s2 };
+        (-0.5 * t * t).exp()
+    };
+    let x = 1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+    return Color::new(x, y, z);
+}
+
+fn fit_lambdas() -> impl Iterator<Item = f64> {
+    let n = ((LAMBDA_MAX - LAMBDA_MIN) / FIT_STEP) as usize;
+    return (0..=n).map(|i| LAMBDA_MIN + i as f64 * FIT_STEP);
+}
+
+/// One over the Y of D65 integrated against the matching functions, so a perfect white
+/// lit by D65 normalized with it has a luminance of one
+fn d65_normalization() -> f64 {
+    let y: f64 = fit_lambdas().map(|l| d65(l) * cie_xyz(l).y() * FIT_STEP).sum();
+    return 1.0 / y;
+}
+
+/// Reflectance spectrum `s(λ) = sigmoid(c0 t² + c1 t + c2)` over `t` running from 0 to 1
+/// across the visible range, the model of Jakob and Hanika (2019). Always within
+/// [0, 1] and smooth, so it is a plausible physical reflectance for its RGB color.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SigmoidSpectrum {
+    c: [f64; 3],
+}
+
+impl SigmoidSpectrum {
+    /// Fits a spectrum whose color under D65 is the linear sRGB `rgb`, which is
+    /// clamped to [0, 1]. Out of gamut colors get the closest fit.
+    pub fn fit(rgb: Color) -> Self {
+        let rgb = Color::new(rgb.x().clamp(0.0, 1.0), rgb.y().clamp(0.0, 1.0), rgb.z().clamp(0.0, 1.0));
+        // Grays are flat spectra, which also covers the black and white limits where
+        // the coefficients run off to infinity
+        if (rgb.x() - rgb.y()).abs() < 1e-9 && (rgb.y() - rgb.z()).abs() < 1e-9 {
+            return SigmoidSpectrum {
+                c: [0.0, 0.0, inverse_sigmoid(rgb.y())],
+            };
+        }
+
+        let eps = 1e-4;
+        let target = Color::new(
+            rgb.x().clamp(eps, 1.0 - eps),
+            rgb.y().clamp(eps, 1.0 - eps),
+            rgb.z().clamp(eps, 1.0 - eps),
+        );
+        let mut c = [0.0, 0.0, inverse_sigmoid((target.x() + target.y() + target.z()) / 3.0)];
+        let mut lambda: f64 = 1e-3;
+        let mut residual = SigmoidSpectrum { c: c }.rgb() - target;
+
+        // Levenberg-Marquardt on the RGB residual with a finite difference Jacobian
+        for _ in 0..100 {
+            if residual.length() < 1e-6 {
+                break;
+            }
+            let mut jac = [[0.0; 3]; 3];
+            for k in 0..3 {
+                let mut ck = c;
+                ck[k] += 1e-4;
+                let d = (SigmoidSpectrum { c: ck }.rgb() - target - residual) / 1e-4;
+                for (i, row) in jac.iter_mut().enumerate() {
+                    row[k] = d[i];
+                }
+            }
+            let jt = Mat3::new(jac).transpose();
+            let damped = jt.mul(&Mat3::new(jac)).add(&Mat3::diagonal(Color::from(lambda)));
+            let step = damped.inverse().apply(jt.apply(residual));
+
+            let candidate = [c[0] - step.x(), c[1] - step.y(), c[2] - step.z()];
+            let candidate_residual = SigmoidSpectrum { c: candidate }.rgb() - target;
+            if candidate.iter().all(|v| v.is_finite()) && candidate_residual.length() < residual.length() {
+                c = candidate;
+                residual = candidate_residual;
+                lambda = (lambda * 0.3).max(1e-9);
+            } else {
+                lambda *= 10.0;
+                if lambda > 1e9 {
+                    break;
+                }
+            }
+        }
+        return SigmoidSpectrum { c: c };
+    }
+
+    pub fn eval(&self, lambda: f64) -> f64 {
+        let t = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN);
+        return sigmoid(self.c[0] * t * t + self.c[1] * t + self.c[2]);
+    }
+
+    /// Linear sRGB color of the spectrum as a reflectance lit by D65
+    pub fn rgb(&self) -> Color {
+        return reflectance_to_rgb(|l| self.eval(l));
+    }
+}
+
+/// Linear sRGB color of the reflectance spectrum `f` lit by D65, so a reflectance of
+/// one everywhere is white
+pub fn reflectance_to_rgb(f: impl Fn(f64) -> f64) -> Color {
+    let mut xyz = Color::default();
+    for l in fit_lambdas() {
+        xyz += (f(l) * d65(l) * FIT_STEP) * cie_xyz(l);
+    }
+    return xyz_to_srgb().apply(xyz * d65_normalization());
+}
+
+/// Smooth reflectance spectrum with the linear sRGB color `rgb`, clamped to [0, 1]
+pub fn reflectance_spectrum(rgb: Color) -> SigmoidSpectrum {
+    return cached_fit(Color::new(rgb.x().clamp(0.0, 1.0), rgb.y().clamp(0.0, 1.0), rgb.z().clamp(0.0, 1.0)));
+}
+
+fn sigmoid(x: f64) -> f64 {
+    if x.is_infinite() {
+        return if x > 0.0 { 1.0 } else { 0.0 };
+    }
+    return 0.5 + x / (2.0 * (1.0 + x * x).sqrt());
+}
+
+fn inverse_sigmoid(y: f64) -> f64 {
+    if y <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if y >= 1.0 {
+        return f64::INFINITY;
+    }
+    let s = 2.0 * y - 1.0;
+    return s / (1.0 - s * s).sqrt();
+}
+
+fn xyz_to_srgb() -> Mat3 {
+    return ColorSpace::LinearSrgb.to_xyz().inverse();
+}
+
+thread_local! {
+    static FITS: RefCell<HashMap<[u64; 3], SigmoidSpectrum>> = RefCell::new(HashMap::new());
+}
+
+/// Fits are cached by color, materials keep asking for the same few
+fn cached_fit(rgb: Color) -> SigmoidSpectrum {
+    let key = rgb.v().map(|v| v.to_bits());
+    return FITS.with(|fits| {
+        let mut fits = fits.borrow_mut();
+        if let Some(s) = fits.get(&key) {
+            return *s;
+        }
+        // Textured inputs could grow the cache without bound
+        if fits.len() > 1 << 16 {
+            fits.clear();
+        }
+        let s = SigmoidSpectrum::fit(rgb);
+        fits.insert(key, s);
+        return s;
+    });
+}
+
+/// Wavelengths carried by one camera sample: a uniformly sampled hero wavelength and
+/// two more spaced evenly around the visible range, so that a `Color` can hold the
+/// sample's value at each of them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampledWavelengths {
+    lambda: [f64; 3],
+}
+
+impl SampledWavelengths {
+    pub fn sample(u: f64) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + u * range;
+        let lambda = [0.0, 1.0, 2.0].map(|k| {
+            let l = hero + k * range / 3.0;
+            if l > LAMBDA_MAX {
+                l - range
+            } else {
+                l
+            }
+        });
+        return Self { lambda: lambda };
+    }
+
+    pub fn hero(&self) -> f64 {
+        return self.lambda[0];
+    }
+
+    pub fn lambda(&self) -> [f64; 3] {
+        return self.lambda;
+    }
+
+    /// Values at the sampled wavelengths of a non emissive quantity such as an albedo
+    /// or BSDF value. Colors above one are scaled down into the sigmoid's range and
+    /// back up afterwards.
+    pub fn reflectance(&self, rgb: Color) -> Color {
+        let rgb = Color::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0));
+        let m = rgb.x().max(rgb.y()).max(rgb.z());
+        if m <= 0.0 {
+            return Color::default();
+        }
+        let scale = if m > 1.0 { 2.0 * m } else { 1.0 };
+        let s = cached_fit(rgb / scale);
+        return scale * Color::new(s.eval(self.lambda[0]), s.eval(self.lambda[1]), s.eval(self.lambda[2]));
+    }
+
+    /// Values at the sampled wavelengths of emitted radiance with the color `rgb`,
+    /// modelled as a reflectance spectrum lit by D65
+    pub fn illuminant(&self, rgb: Color) -> Color {
+        let r = self.reflectance(rgb);
+        let k = d65_normalization();
+        return Color::new(
+            r.x() * d65(self.lambda[0]) * k,
+            r.y() * d65(self.lambda[1]) * k,
+            r.z() * d65(self.lambda[2]) * k,
+        );
+    }
+
+    /// Monte Carlo estimate of the linear sRGB color of a spectrum given by its values
+    /// at the sampled wavelengths
+    pub fn to_rgb(&self, values: Color) -> Color {
+        let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+        let mut xyz = Color::default();
+        for i in 0..3 {
+            xyz += (values[i] / pdf) * cie_xyz(self.lambda[i]);
+        }
+        return xyz_to_srgb().apply(xyz / 3.0);
+    }
+}
+
+#[test]
+fn matching_functions_integrate_like_cie() {
+    let y: f64 = fit_lambdas().map(|l| cie_xyz(l).y() * FIT_STEP).sum();
+    assert!((y - 106.86).abs() < 1.5, "{}", y);
+    assert_eq!(d65(560.0), 100.0);
+    assert!((d65(565.0) - 98.165).abs() < 1e-9);
+}
+
+#[test]
+fn sigmoid_fit_reproduces_colors() {
+    for rgb in [
+        Color::new(0.8, 0.8, 0.8),
+        Color::new(0.2, 0.5, 0.8),
+        Color::new(0.7, 0.3, 0.1),
+        Color::new(0.1, 0.6, 0.2),
+        Color::new(1.0, 1.0, 1.0),
+    ] {
+        let s = SigmoidSpectrum::fit(rgb);
+        assert!((s.rgb() - rgb).length() < 5e-3, "{:?} -> {:?}", rgb, s.rgb());
+        for l in fit_lambdas() {
+            assert!((0.0..=1.0).contains(&s.eval(l)));
+        }
+    }
+}
+
+#[test]
+fn spectral_estimates_converge_to_rgb() {
+    use crate::util::{random_f64, seed_random};
+
+    seed_random(11);
+    for rgb in [Color::new(1, 1, 1), Color::new(0.8, 0.3, 0.1), Color::new(3.0, 1.5, 0.5)] {
+        let n = 20000;
+        let mut sum = Color::default();
+        for _ in 0..n {
+            let wl = SampledWavelengths::sample(random_f64());
+            sum += wl.to_rgb(wl.illuminant(rgb));
+        }
+        let estimate = sum / n as f64;
+        assert!((estimate - rgb).length() < 0.03 * rgb.length(), "{:?} -> {:?}", rgb, estimate);
+    }
+}
@@ -0,0 +1,80 @@
+use std::f64::consts::PI;
+
+use num::Complex;
+
+/// What lies underneath a thin film
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Substrate {
+    /// Transparent medium with the given refractive index
+    Dielectric(f64),
+    /// Conductor reflecting the given fraction of light, with the phase flip of an
+    /// ideal mirror
+    Mirror(f64),
+}
+
+/// Reflectance of a film of index `n_film` and `thickness` nm lying on `substrate`,
+/// for unpolarized light of wavelength `lambda` nm arriving from a medium of index
+/// `n_incident` at an angle with cosine `cos_theta`.
+///
+/// Sums the infinitely many reflections bouncing inside the film with the Airy
+/// formula `r = (r01 + r12 e^iδ) / (1 + r01 r12 e^iδ)`, where `δ` is the phase the
+/// light picks up crossing the film and back.
+pub fn airy_reflectance(
+    cos_theta: f64,
+    n_incident: f64,
+    n_film: f64,
+    thickness: f64,
+    substrate: Substrate,
+    lambda: f64,
+) -> f64 {
+    let cos_i = Complex::new(cos_theta.clamp(0.0, 1.0), 0.0);
+    let sin2_i = Complex::new(1.0, 0.0) - cos_i * cos_i;
+    // Snell's law, going complex past the critical angle
+    let cos_in = |n: f64| (Complex::new(1.0, 0.0) - sin2_i * (n_incident / n).powi(2)).sqrt();
+    let cos_f = cos_in(n_film);
+
+    let (r01_s, r01_p) = fresnel(n_incident, cos_i, n_film, cos_f);
+    let (r12_s, r12_p) = match substrate {
+        Substrate::Dielectric(n) => fresnel(n_film, cos_f, n, cos_in(n)),
+        Substrate::Mirror(r) => {
+            let a = Complex::new(-r.clamp(0.0, 1.0).sqrt(), 0.0);
+            (a, a)
+        }
+    };
+
+    let delta = 4.0 * PI * n_film * thickness / lambda * cos_f;
+    let phase = (Complex::<f64>::i() * delta).exp();
+    let airy = |r01: Complex<f64>, r12: Complex<f64>| {
+        let r = (r01 + r12 * phase) / (Complex::new(1.0, 0.0) + r01 * r12 * phase);
+        r.norm_sqr()
+    };
+    return (0.5 * (airy(r01_s, r12_s) + airy(r01_p, r12_p))).clamp(0.0, 1.0);
+}
+
+/// Fresnel amplitude coefficients for s and p polarized light going from index `n1`
+/// to `n2`
+fn fresnel(n1: f64, cos1: Complex<f64>, n2: f64, cos2: Complex<f64>) -> (Complex<f64>, Complex<f64>) {
+    let s = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+    let p = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+    return (s, p);
+}
+
+#[test]
+fn invisible_films_leave_plain_fresnel() {
+    let r = airy_reflectance(1.0, 1.0, 1.33, 0.0, Substrate::Dielectric(1.5), 550.0);
+    assert!((r - 0.04).abs() < 1e-12);
+    let r = airy_reflectance(0.7, 1.0, 1.0, 250.0, Substrate::Mirror(0.9), 550.0);
+    assert!((r - 0.9).abs() < 1e-12);
+}
+
+#[test]
+fn quarter_wave_coating_cancels_reflection() {
+    let n_film = 1.5f64.sqrt();
+    let thickness = 550.0 / (4.0 * n_film);
+    let r = airy_reflectance(1.0, 1.0, n_film, thickness, Substrate::Dielectric(1.5), 550.0);
+    assert!(r < 1e-12);
+    // Other wavelengths come back, which is what colors the film
+    assert!(airy_reflectance(1.0, 1.0, n_film, thickness, Substrate::Dielectric(1.5), 400.0) > 1e-3);
+    // Beyond the critical angle everything is reflected
+    assert!((airy_reflectance(0.1, 1.5, 1.33, 300.0, Substrate::Dielectric(1.0), 550.0) - 1.0).abs() < 1e-9);
+}
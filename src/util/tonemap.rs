@@ -0,0 +1,168 @@
+use std::str::FromStr;
+
+use crate::util::color::{luminance, Color};
+
+/// Operator compressing scene referred radiance into the displayable [0, 1] range
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMap {
+    /// Values above one are clipped
+    #[default]
+    Clamp,
+    /// `L / (1 + L)` on luminance, so hues are kept
+    Reinhard,
+    /// Reinhard scaled so luminance `white` maps to exactly one
+    ExtendedReinhard { white: f64 },
+    /// John Hable's filmic curve from Uncharted 2
+    Hable,
+    /// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms
+    Aces,
+}
+
+impl ToneMap {
+    pub fn apply(&self, c: Color) -> Color {
+        return match *self {
+            ToneMap::Clamp => clamp01(c),
+            ToneMap::Reinhard => scale_luminance(c, |l| l / (1.0 + l)),
+            ToneMap::ExtendedReinhard { white } => {
+                let w2 = (white * white).max(1e-12);
+                scale_luminance(c, |l| l * (1.0 + l / w2) / (1.0 + l))
+            }
+            ToneMap::Hable => {
+                // Exposure bias and linear white point from the original talk
+                let white = 11.2;
+                let scale = 1.0 / hable_partial(white);
+                clamp01(Color::new(
+                    hable_partial(2.0 * c.x()) * scale,
+                    hable_partial(2.0 * c.y()) * scale,
+                    hable_partial(2.0 * c.z()) * scale,
+                ))
+            }
+            ToneMap::Aces => aces_fitted(c),
+        };
+    }
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    /// Parses `clamp`, `reinhard`, `reinhard:<white>`, `hable` or `aces`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        if let Some(white) = s.strip_prefix("reinhard:") {
+            let white = white
+                .parse::<f64>()
+                .map_err(|_| format!("invalid Reinhard white point `{}`", white))?;
+            return Ok(ToneMap::ExtendedReinhard { white: white });
+        }
+        return match s.as_str() {
+            "clamp" | "none" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "hable" | "uncharted2" => Ok(ToneMap::Hable),
+            "aces" => Ok(ToneMap::Aces),
+            _ => Err(format!("unknown tone mapping operator `{}`", s)),
+        };
+    }
+}
+
+/// Scene to display transform: exposure, then a tone mapping operator. The result is
+/// still linear, the sRGB OETF is applied when writing 8 bit output.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DisplayTransform {
+    /// Exposure adjustment in stops, each one doubles the brightness
+    pub exposure: f64,
+    pub tone_map: ToneMap,
+}
+
+impl DisplayTransform {
+    pub fn new(exposure: f64, tone_map: ToneMap) -> Self {
+        Self {
+            exposure: exposure,
+            tone_map: tone_map,
+        }
+    }
+
+    pub fn apply(&self, c: Color) -> Color {
+        return self.tone_map.apply(c * 2f64.powf(self.exposure));
+    }
+}
+
+fn clamp01(c: Color) -> Color {
+    return Color::new(c.x().clamp(0.0, 1.0), c.y().clamp(0.0, 1.0), c.z().clamp(0.0, 1.0));
+}
+
+fn scale_luminance(c: Color, curve: impl Fn(f64) -> f64) -> Color {
+    let l = luminance(&c);
+    if l <= 0.0 {
+        return Color::default();
+    }
+    return clamp01(c * (curve(l) / l));
+}
+
+fn hable_partial(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+fn aces_fitted(c: Color) -> Color {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    let input = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    let output = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+    let mul = |m: [[f64; 3]; 3], v: Color| {
+        Color::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    };
+    let rrt_odt = |v: f64| {
+        let a = v * (v + 0.0245786) - 0.000090537;
+        let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+        a / b
+    };
+
+    let v = mul(input, c);
+    let v = Color::new(rrt_odt(v.x()), rrt_odt(v.y()), rrt_odt(v.z()));
+    return clamp01(mul(output, v));
+}
+
+#[test]
+fn tone_maps_stay_displayable_and_monotonic() {
+    let operators = [
+        ToneMap::Clamp,
+        ToneMap::Reinhard,
+        ToneMap::ExtendedReinhard { white: 4.0 },
+        ToneMap::Hable,
+        ToneMap::Aces,
+    ];
+    for op in operators {
+        let mut prev = -1.0;
+        for k in 0..40 {
+            let v = op.apply(Color::from(0.01 * 1.3f64.powi(k))).x();
+            assert!((0.0..=1.0).contains(&v), "{:?}", op);
+            assert!(v >= prev, "{:?}", op);
+            prev = v;
+        }
+        assert_eq!(op.apply(Color::default()).x().max(0.0), 0.0);
+    }
+
+    assert_eq!(ToneMap::ExtendedReinhard { white: 4.0 }.apply(Color::from(4.0)), Color::from(1.0));
+    assert!((ToneMap::Hable.apply(Color::from(5.6)).x() - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn exposure_and_operator_parsing() {
+    let t = DisplayTransform::new(1.0, ToneMap::Clamp);
+    assert_eq!(t.apply(Color::from(0.25)), Color::from(0.5));
+    assert_eq!("ACES".parse::<ToneMap>(), Ok(ToneMap::Aces));
+    assert_eq!("reinhard:8".parse::<ToneMap>(), Ok(ToneMap::ExtendedReinhard { white: 8.0 }));
+    assert!("filmic".parse::<ToneMap>().is_err());
+}
@@ -4,9 +4,8 @@ use std::{
 };
 
 use num::ToPrimitive;
-use rand::random;
 
-use super::random_range;
+use super::{random_f64, random_range};
 
 #[derive(Debug, PartialEq, Copy)]
 pub struct Vec3 {
@@ -387,7 +386,7 @@ impl Vec3 {
     }
 
     pub fn random() -> Self {
-        return Vec3::new(random::<f64>(), random::<f64>(), random::<f64>());
+        return Vec3::new(random_f64(), random_f64(), random_f64());
     }
 
     pub fn rand_range<T>(min: T, max: T) -> Self
@@ -428,6 +427,16 @@ impl Vec3 {
     pub fn reflect(v: Vec3, n: Vec3) -> Self {
         return v - 2.0 * dot(v, n) * n;
     }
+
+    /// Refracts unit vector `uv` through a surface with unit normal `n` facing it,
+    /// `eta_ratio` being the index on the incoming side over the index on the other
+    #[inline]
+    pub fn refract(uv: Vec3, n: Vec3, eta_ratio: f64) -> Self {
+        let cos_theta = dot(-uv, n).min(1.0);
+        let r_out_perp = eta_ratio * (uv + cos_theta * n);
+        let r_out_parallel = -(1.0 - r_out_perp.length_squared()).abs().sqrt() * n;
+        return r_out_perp + r_out_parallel;
+    }
 }
 
 pub type Point3 = Vec3;
@@ -483,3 +492,13 @@ fn vec3_div() {
     let c = a / b;
     assert_eq!(c, Vec3::new(0.5, 2.0, 10.0))
 }
+
+#[test]
+fn vec3_refract_follows_snell() {
+    let n = Vec3::new(0.0, 1.0, 0.0);
+    let uv = Vec3::new(1.0, -1.0, 0.0).to_normal();
+    let r = Vec3::refract(uv, n, 1.0 / 1.5);
+    assert!((r.length() - 1.0).abs() < 1e-12);
+    assert!((r.x() * 1.5 - uv.x()).abs() < 1e-12);
+    assert!(r.y() < 0.0);
+}
@@ -0,0 +1,237 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+};
+
+use crate::util::{color::Color, vec::Vec3};
+
+/// Dense voxel grid with a density channel and optional per voxel albedo and emission.
+///
+/// On disk a grid is a single text header line `VOL <nx> <ny> <nz> <channels>`
+/// followed by `nx * ny * nz * channels` little endian `f32` values with x
+/// varying fastest. Channels are density, then albedo rgb, then emission rgb,
+/// so valid channel counts are 1, 4 and 7.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxelGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    density: Vec<f64>,
+    albedo: Option<Vec<Color>>,
+    emission: Option<Vec<Color>>,
+}
+
+/// Interpolated contents of a grid at a point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoxelSample {
+    pub density: f64,
+    pub albedo: Color,
+    pub emission: Color,
+}
+
+impl VoxelGrid {
+    pub fn new(nx: usize, ny: usize, nz: usize, density: Vec<f64>) -> Self {
+        assert_eq!(density.len(), nx * ny * nz, "density size does not match grid resolution");
+        Self {
+            nx: nx,
+            ny: ny,
+            nz: nz,
+            density: density,
+            albedo: None,
+            emission: None,
+        }
+    }
+
+    pub fn with_albedo(mut self, albedo: Vec<Color>) -> Self {
+        assert_eq!(albedo.len(), self.density.len(), "albedo size does not match grid resolution");
+        self.albedo = Some(albedo);
+        return self;
+    }
+
+    pub fn with_emission(mut self, emission: Vec<Color>) -> Self {
+        assert_eq!(emission.len(), self.density.len(), "emission size does not match grid resolution");
+        self.emission = Some(emission);
+        return self;
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        return Self::read(&mut BufReader::new(File::open(path)?));
+    }
+
+    pub fn read<R: BufRead>(reader: &mut R) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        if fields.len() != 5 || fields[0] != "VOL" {
+            return Err(invalid("expected `VOL <nx> <ny> <nz> <channels>` header"));
+        }
+        let dims: Vec<usize> = fields[1..]
+            .iter()
+            .map(|f| f.parse::<usize>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid("grid header contains a non integer field"))?;
+        let (nx, ny, nz, channels) = (dims[0], dims[1], dims[2], dims[3]);
+        if !matches!(channels, 1 | 4 | 7) {
+            return Err(invalid("grid must have 1, 4 or 7 channels"));
+        }
+
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(invalid("grid must have at least one voxel along each axis"));
+        }
+        let count = nx
+            .checked_mul(ny)
+            .and_then(|n| n.checked_mul(nz))
+            .filter(|n| n.checked_mul(channels * 4).is_some())
+            .ok_or_else(|| invalid("grid resolution is too large"))?;
+        let mut bytes = vec![0u8; count * channels * 4];
+        reader.read_exact(&mut bytes)?;
+        let values: Vec<f64> = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect();
+
+        let channel_color = |offset: usize| -> Vec<Color> {
+            (0..count)
+                .map(|i| {
+                    let v = &values[i * channels + offset..i * channels + offset + 3];
+                    Color::new(v[0], v[1], v[2])
+                })
+                .collect()
+        };
+
+        let mut grid = Self::new(nx, ny, nz, (0..count).map(|i| values[i * channels]).collect());
+        if channels >= 4 {
+            grid = grid.with_albedo(channel_color(1));
+        }
+        if channels == 7 {
+            grid = grid.with_emission(channel_color(4));
+        }
+        return Ok(grid);
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let channels = if self.emission.is_some() {
+            7
+        } else if self.albedo.is_some() {
+            4
+        } else {
+            1
+        };
+        writeln!(writer, "VOL {} {} {} {}", self.nx, self.ny, self.nz, channels)?;
+
+        let white = Color::new(1, 1, 1);
+        for i in 0..self.density.len() {
+            let mut values = vec![self.density[i]];
+            if channels >= 4 {
+                let a = self.albedo.as_ref().map_or(white, |a| a[i]);
+                values.extend(a.v());
+            }
+            if channels == 7 {
+                values.extend(self.emission.as_ref().unwrap()[i].v());
+            }
+            for v in values {
+                writer.write_all(&(v as f32).to_le_bytes())?;
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn max_density(&self) -> f64 {
+        return self.density.iter().cloned().fold(0.0, f64::max);
+    }
+
+    /// Trilinearly interpolates the grid at `p`, given in [0, 1] grid local coordinates
+    pub fn sample(&self, p: Vec3) -> VoxelSample {
+        let (ix, fx) = Self::cell(p.x(), self.nx);
+        let (iy, fy) = Self::cell(p.y(), self.ny);
+        let (iz, fz) = Self::cell(p.z(), self.nz);
+
+        let mut density = 0.0;
+        let mut albedo = Color::default();
+        let mut emission = Color::default();
+        for corner in 0..8 {
+            let dx = corner & 1;
+            let dy = (corner >> 1) & 1;
+            let dz = (corner >> 2) & 1;
+            let w = (if dx == 1 { fx } else { 1.0 - fx })
+                * (if dy == 1 { fy } else { 1.0 - fy })
+                * (if dz == 1 { fz } else { 1.0 - fz });
+            if w == 0.0 {
+                continue;
+            }
+
+            let idx = self.index(
+                (ix + dx).min(self.nx - 1),
+                (iy + dy).min(self.ny - 1),
+                (iz + dz).min(self.nz - 1),
+            );
+            density += w * self.density[idx];
+            if let Some(a) = &self.albedo {
+                albedo += w * a[idx];
+            }
+            if let Some(e) = &self.emission {
+                emission += w * e[idx];
+            }
+        }
+
+        if self.albedo.is_none() {
+            albedo = Color::new(1, 1, 1);
+        }
+
+        VoxelSample {
+            density: density,
+            albedo: albedo,
+            emission: emission,
+        }
+    }
+
+    #[inline]
+    fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        return x + self.nx * (y + self.ny * z);
+    }
+
+    /// Splits a normalized coordinate into the lower voxel index and the
+    /// interpolation weight towards the next one, treating values as voxel centered
+    #[inline]
+    fn cell(u: f64, n: usize) -> (usize, f64) {
+        let x = (u * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+        let i = x.floor();
+        return (i as usize, x - i);
+    }
+}
+
+#[test]
+fn voxel_trilinear_sample() {
+    let grid = VoxelGrid::new(2, 1, 1, vec![0.0, 4.0]);
+
+    assert_eq!(grid.sample(Vec3::new(0.0, 0.5, 0.5)).density, 0.0);
+    assert_eq!(grid.sample(Vec3::new(0.5, 0.5, 0.5)).density, 2.0);
+    assert_eq!(grid.sample(Vec3::new(1.0, 0.5, 0.5)).density, 4.0);
+    assert_eq!(grid.sample(Vec3::new(0.5, 0.5, 0.5)).albedo, Color::new(1, 1, 1));
+    assert_eq!(grid.max_density(), 4.0);
+}
+
+#[test]
+fn voxel_write_read_roundtrip() {
+    let grid = VoxelGrid::new(1, 2, 1, vec![0.5, 1.5])
+        .with_albedo(vec![Color::new(0.25, 0.5, 1.0), Color::new(1, 1, 1)])
+        .with_emission(vec![Color::new(2, 0, 0), Color::new(0, 0, 3)]);
+
+    let mut bytes = Vec::new();
+    grid.write(&mut bytes).unwrap();
+    let read = VoxelGrid::read(&mut bytes.as_slice()).unwrap();
+
+    assert_eq!(read, grid);
+    assert!(VoxelGrid::read(&mut "VOL 1 1 1 2\n".as_bytes()).is_err());
+}
+
+#[test]
+fn voxel_read_rejects_bad_resolutions() {
+    for header in ["VOL 0 2 2 1\n", "VOL 2 2 0 1\n", "VOL 4294967296 4294967296 2 1\n"] {
+        let err = VoxelGrid::read(&mut header.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", header);
+    }
+}